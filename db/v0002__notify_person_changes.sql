@@ -0,0 +1,54 @@
+CREATE SEQUENCE person_changes_seq;
+
+CREATE OR REPLACE FUNCTION notify_person_changed() RETURNS TRIGGER as $notify_person_changed$
+BEGIN
+  IF (TG_OP = 'INSERT') THEN
+    PERFORM PG_NOTIFY(
+      'person_changes',
+      JSON_BUILD_OBJECT(
+        'seq', NEXTVAL('person_changes_seq'),
+        'tipo', 'created',
+        'pessoa', JSON_BUILD_OBJECT(
+          'id', NEW.id,
+          'nome', NEW.name,
+          'apelido', NEW.nick,
+          'nascimento', NEW.birth_date,
+          'stack', ARRAY_TO_JSON(NEW.stack::VARCHAR(32)[])
+        )
+      )::TEXT
+    );
+  ELSIF (TG_OP = 'UPDATE') THEN
+    PERFORM PG_NOTIFY(
+      'person_changes',
+      JSON_BUILD_OBJECT(
+        'seq', NEXTVAL('person_changes_seq'),
+        'tipo', 'updated',
+        'pessoa', JSON_BUILD_OBJECT(
+          'id', NEW.id,
+          'nome', NEW.name,
+          'apelido', NEW.nick,
+          'nascimento', NEW.birth_date,
+          'stack', ARRAY_TO_JSON(NEW.stack::VARCHAR(32)[])
+        ),
+        'apelido_anterior', OLD.nick
+      )::TEXT
+    );
+  ELSIF (TG_OP = 'DELETE') THEN
+    PERFORM PG_NOTIFY(
+      'person_changes',
+      JSON_BUILD_OBJECT(
+        'seq', NEXTVAL('person_changes_seq'),
+        'tipo', 'deleted',
+        'id', OLD.id,
+        'apelido', OLD.nick
+      )::TEXT
+    );
+  END IF;
+  RETURN NULL;
+END;
+$notify_person_changed$ LANGUAGE plpgsql;
+
+DROP TRIGGER notify_person_created ON people;
+DROP FUNCTION notify_person_created();
+
+CREATE TRIGGER notify_person_changed AFTER INSERT OR UPDATE OR DELETE ON people FOR EACH ROW EXECUTE PROCEDURE notify_person_changed();
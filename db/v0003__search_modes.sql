@@ -0,0 +1,8 @@
+ALTER TABLE people ADD COLUMN search_document TSVECTOR GENERATED ALWAYS AS (
+  TO_TSVECTOR(
+    'portuguese',
+    name || ' ' || nick || ' ' || COALESCE(ARRAY_TO_STRING_IMMUTABLE(stack, ' '), '')
+  )
+) STORED;
+
+CREATE INDEX people_search_document_index ON people USING GIN (search_document);
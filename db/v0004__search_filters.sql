@@ -0,0 +1,10 @@
+CREATE OR REPLACE FUNCTION LOWER_ARRAY_IMMUTABLE (
+  arr TEXT[]
+) RETURNS TEXT[] IMMUTABLE PARALLEL SAFE LANGUAGE SQL AS $$
+SELECT ARRAY(SELECT LOWER(item) FROM UNNEST(arr) AS item) $$;
+
+CREATE INDEX people_name_index ON people USING GIST (name gist_trgm_ops);
+
+CREATE INDEX people_stack_index ON people USING GIN (LOWER_ARRAY_IMMUTABLE(stack));
+
+CREATE INDEX people_birth_date_index ON people (birth_date);
@@ -0,0 +1,22 @@
+-- Spread over a few rows, so concurrent inserts don't all wait on the same row lock.
+CREATE TABLE people_count (
+  slot SMALLINT PRIMARY KEY,
+  count BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO people_count (slot) SELECT GENERATE_SERIES(0, 15);
+
+UPDATE people_count SET count = (SELECT COUNT(*) FROM people) WHERE slot = 0;
+
+CREATE OR REPLACE FUNCTION count_people_changed() RETURNS TRIGGER as $count_people_changed$
+BEGIN
+  IF (TG_OP = 'INSERT') THEN
+    UPDATE people_count SET count = count + 1 WHERE slot = PG_BACKEND_PID() % 16;
+  ELSIF (TG_OP = 'DELETE') THEN
+    UPDATE people_count SET count = count - 1 WHERE slot = PG_BACKEND_PID() % 16;
+  END IF;
+  RETURN NULL;
+END;
+$count_people_changed$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_people_changed AFTER INSERT OR DELETE ON people FOR EACH ROW EXECUTE PROCEDURE count_people_changed();
@@ -0,0 +1,35 @@
+-- Who owns each nick. People written behind reserve theirs here before they are answered, so
+-- the nick can't be handed out twice while their rows wait in a batch.
+CREATE TABLE person_nicks (
+  nick VARCHAR(32) PRIMARY KEY,
+  id UUID NOT NULL
+);
+
+INSERT INTO person_nicks (nick, id) SELECT nick, id FROM people;
+
+-- Rows inserted without a reservation claim their nick here too, and fail like the unique
+-- constraint would if somebody else reserved it first.
+CREATE OR REPLACE FUNCTION claim_person_nick() RETURNS TRIGGER as $claim_person_nick$
+BEGIN
+  IF (TG_OP = 'UPDATE' AND NEW.nick = OLD.nick) THEN
+    RETURN NULL;
+  END IF;
+
+  IF (TG_OP IN ('UPDATE', 'DELETE')) THEN
+    DELETE FROM person_nicks WHERE nick = OLD.nick AND id = OLD.id;
+  END IF;
+
+  IF (TG_OP IN ('INSERT', 'UPDATE')) THEN
+    INSERT INTO person_nicks (nick, id) VALUES (NEW.nick, NEW.id) ON CONFLICT (nick) DO NOTHING;
+    IF NOT FOUND AND NOT EXISTS (
+      SELECT FROM person_nicks WHERE nick = NEW.nick AND id = NEW.id
+    ) THEN
+      RAISE unique_violation USING MESSAGE = 'nick is already taken', CONSTRAINT = 'person_nicks_pkey';
+    END IF;
+  END IF;
+
+  RETURN NULL;
+END;
+$claim_person_nick$ LANGUAGE plpgsql;
+
+CREATE TRIGGER claim_person_nick AFTER INSERT OR UPDATE OF nick OR DELETE ON people FOR EACH ROW EXECUTE PROCEDURE claim_person_nick();
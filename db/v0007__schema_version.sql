@@ -0,0 +1,9 @@
+-- The versions of the migrations applied so far. Every migration from this one on records its own
+-- version here, the number in its file name, so instances can tell whether the schema they need
+-- is there.
+CREATE TABLE schema_version (
+  version INTEGER PRIMARY KEY,
+  applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO schema_version (version) VALUES (7);
@@ -1,24 +1,51 @@
-use std::{env, net::SocketAddr, sync::Arc};
+use std::{env, error::Error, net::SocketAddr, sync::Arc, time::Instant};
 
 use axum::{
-    extract::{Path, Query, State},
-    http::{header, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
+    body::Bytes,
+    extract::{
+        rejection::{PathRejection, QueryRejection},
+        MatchedPath, Path, Query, State,
+    },
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
 };
-use rinha_core::NewPerson;
-use serde::Deserialize;
+use rinha_core::{
+    ApiError, AsyncPersonRepository, BirthDateRules, ErrorCode, InMemoryRepository, LogFormat,
+    Metrics, NewPerson, PayloadError, PersistenceError, Person, RepositoryOptions, SearchQuery,
+    SearchRules, DEFAULT_LOG_FILTER, METRICS_CONTENT_TYPE, NEXT_CURSOR_HEADER,
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tower_http::{
+    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tracing::{error, Level};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
-use crate::persistence::{PersistenceError, PostgresRepository};
+use crate::persistence::PostgresRepository;
 
 mod persistence;
 
-type AppState = Arc<PostgresRepository>;
+type AppState = Arc<dyn AsyncPersonRepository>;
 
 #[tokio::main]
 async fn main() {
+    let log_format = env::var("LOG_FORMAT")
+        .ok()
+        .and_then(|format| format.parse::<LogFormat>().ok())
+        .unwrap_or_default();
+    let logger = tracing_subscriber::fmt().with_env_filter(
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER)),
+    );
+    match log_format {
+        LogFormat::Pretty => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+
     let port = env::var("PORT")
         .ok()
         .and_then(|port| port.parse::<u16>().ok())
@@ -32,68 +59,263 @@ async fn main() {
         .and_then(|port| port.parse::<u32>().ok())
         .unwrap_or(30);
 
-    let repo = PostgresRepository::connect(&database_url, database_pool_size)
-        .await
-        .unwrap();
+    BirthDateRules::from_env()
+        .install()
+        .expect("birth date rules are installed once");
+    SearchRules::from_env()
+        .install()
+        .expect("search rules are installed once");
 
-    let app_state = Arc::new(repo);
+    let app_state: AppState = match env::var("PERSISTENCE").as_deref() {
+        Ok("memory") => Arc::new(InMemoryRepository::new()),
+        _ => {
+            let repo = PostgresRepository::connect(
+                &database_url,
+                database_pool_size,
+                RepositoryOptions::from_env(),
+            )
+            .expect("DATABASE_URL is a valid connection string");
+            repo.wait_for_warm_up().await;
+            Arc::new(repo)
+        }
+    };
+
+    let metrics = Arc::new(Metrics::new());
 
     let app = Router::new()
-        .route("/pessoas", get(search_people))
-        .route("/pessoas/:id", get(find_person))
-        .route("/pessoas", post(create_person))
-        .route("/contagem-pessoas", get(count_people))
-        .with_state(app_state);
+        .route(
+            "/pessoas",
+            get(search_people)
+                .post(create_person)
+                .fallback(method_not_allowed),
+        )
+        .route(
+            "/pessoas/:id",
+            get(find_person)
+                .put(update_person)
+                .patch(patch_person)
+                .delete(delete_person)
+                .fallback(method_not_allowed),
+        )
+        .route(
+            "/contagem-pessoas",
+            get(count_people).fallback(method_not_allowed),
+        )
+        .route("/metrics", get(render_metrics).fallback(method_not_allowed))
+        .route("/health/live", get(live).fallback(method_not_allowed))
+        .route("/health/ready", get(ready).fallback(method_not_allowed))
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            track_request,
+        ))
+        .layer(Extension(metrics))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Micros),
+                ),
+        )
+        .with_state(app_state.clone());
 
     axum::Server::bind(&SocketAddr::from(([0, 0, 0, 0], port)))
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    if let Err(err) = app_state.flush().await {
+        error!(error = %err, "failed to flush people on shutdown");
+    }
 }
 
-#[derive(Deserialize)]
-struct PersonSearchQuery {
-    #[serde(rename = "t")]
-    query: String,
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
 }
+
 async fn search_people(
     State(people): State<AppState>,
-    Query(PersonSearchQuery { query }): Query<PersonSearchQuery>,
-) -> impl IntoResponse {
-    match people.search_people(&query).await {
-        Ok(people) => Ok(Json(people)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    // Reports what serde said about the query string, without axum's own wording around it.
+    let Query(query) = query.map_err(|err| {
+        let mut cause: &dyn Error = &err;
+        while let Some(source) = cause.source() {
+            cause = source;
+        }
+        ApiError::new(ErrorCode::InvalidParameter, cause.to_string())
+    })?;
+    query.validate()?;
+
+    let people = people.search_people(&query).await?;
+    let next_cursor = query
+        .next_cursor(&people)
+        .map(|cursor| [(NEXT_CURSOR_HEADER, cursor.to_string())]);
+    Ok((next_cursor, Json(people)))
 }
 
 async fn find_person(
     State(people): State<AppState>,
-    Path(person_id): Path<Uuid>,
-) -> impl IntoResponse {
-    match people.find_person(person_id).await {
-        Ok(Some(person)) => Ok(Json(person)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    person_id: Result<Path<Uuid>, PathRejection>,
+) -> Result<Json<Person>, AppError> {
+    let Path(person_id) = person_id.map_err(|_| ApiError::not_found())?;
+    match people.find_person(person_id).await? {
+        Some(person) => Ok(Json(person)),
+        None => Err(ApiError::not_found().into()),
     }
 }
 
 async fn create_person(
     State(people): State<AppState>,
-    Json(new_person): Json<NewPerson>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
+    let new_person = NewPerson::from_json(&body)?;
+    let id = people.create_person(new_person).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/pessoas/{}", id))],
+    ))
+}
+
+async fn update_person(
+    State(people): State<AppState>,
+    person_id: Result<Path<Uuid>, PathRejection>,
+    body: Bytes,
+) -> Result<Json<Person>, AppError> {
+    let Path(person_id) = person_id.map_err(|_| ApiError::not_found())?;
+    let person = NewPerson::from_json(&body)?;
+    save_person(&people, person_id, person).await
+}
+
+async fn patch_person(
+    State(people): State<AppState>,
+    person_id: Result<Path<Uuid>, PathRejection>,
+    body: Bytes,
+) -> Result<Json<Person>, AppError> {
+    let Path(person_id) = person_id.map_err(|_| ApiError::not_found())?;
+    let current = people
+        .find_person(person_id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let person = NewPerson::from_patch(&current, &body)?;
+    save_person(&people, person_id, person).await
+}
+
+async fn save_person(
+    people: &AppState,
+    person_id: Uuid,
+    person: NewPerson,
+) -> Result<Json<Person>, AppError> {
+    match people.update_person(person_id, person).await? {
+        Some(person) => Ok(Json(person)),
+        None => Err(ApiError::not_found().into()),
+    }
+}
+
+async fn delete_person(
+    State(people): State<AppState>,
+    person_id: Result<Path<Uuid>, PathRejection>,
+) -> Result<StatusCode, AppError> {
+    let Path(person_id) = person_id.map_err(|_| ApiError::not_found())?;
+    match people.delete_person(person_id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::not_found().into()),
+    }
+}
+
+async fn count_people(State(people): State<AppState>) -> Result<Json<u64>, AppError> {
+    Ok(Json(people.count_people().await?))
+}
+
+async fn render_metrics(
+    State(people): State<AppState>,
+    Extension(metrics): Extension<Arc<Metrics>>,
 ) -> impl IntoResponse {
-    match people.create_person(new_person).await {
-        Ok(id) => Ok((
-            StatusCode::CREATED,
-            [(header::LOCATION, format!("/pessoas/{}", id))],
-        )),
-        Err(PersistenceError::UniqueViolation) => Err(StatusCode::UNPROCESSABLE_ENTITY),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    (
+        [(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+        metrics.render(&people.stats()),
+    )
+}
+
+/// Answers as long as the process is serving requests at all.
+async fn live() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn ready(State(people): State<AppState>) -> impl IntoResponse {
+    let readiness = people.readiness().await;
+    (
+        StatusCode::from_u16(readiness.status()).unwrap(),
+        [(header::CONTENT_TYPE, "application/json")],
+        readiness.to_json(),
+    )
+}
+
+async fn track_request<B>(
+    State(metrics): State<Arc<Metrics>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = req.method().clone();
+    let route = req.extensions().get::<MatchedPath>().cloned();
+    let started = Instant::now();
+
+    let res = next.run(req).await;
+    metrics.record(
+        route.as_ref().map(MatchedPath::as_str),
+        method.as_str(),
+        res.status().as_u16(),
+        started.elapsed(),
+    );
+    res
+}
+
+async fn not_found() -> AppError {
+    ApiError::new(ErrorCode::NotFound, "no such route").into()
+}
+
+async fn method_not_allowed() -> AppError {
+    ApiError::method_not_allowed().into()
+}
+
+/// Renders an [`ApiError`] as the response of a failed request.
+struct AppError(ApiError);
+
+impl From<ApiError> for AppError {
+    fn from(err: ApiError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<PayloadError> for AppError {
+    fn from(err: PayloadError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl From<PersistenceError> for AppError {
+    fn from(err: PersistenceError) -> Self {
+        if let PersistenceError::DatabaseError(err) = &err {
+            error!(error = %err, "database error");
+        }
+        Self(err.into())
     }
 }
 
-async fn count_people(State(people): State<AppState>) -> impl IntoResponse {
-    match people.count_people().await {
-        Ok(count) => Ok(Json(count)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::from_u16(self.0.status()).unwrap(),
+            [(header::CONTENT_TYPE, "application/json")],
+            self.0.to_json(),
+        )
+            .into_response()
     }
 }
@@ -1,72 +1,323 @@
-use std::{error::Error, sync::Arc};
+use std::{future::Future, sync::Arc};
 
-use dashmap::{DashMap, DashSet};
-use rinha_core::{NewPerson, Person};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use rinha_core::{
+    check_schema_version, normalize_stack, AsyncPersonRepository, Backoff, BatchWrite, CacheWarmUp,
+    CatchUp, CountMode, HealthCheck, ListenerStatus, NewPerson, NickFilter, PendingPeople,
+    PersistenceError, PersistenceResult, Person, PersonCache, PersonChange, PoolStats, Readiness,
+    RepositoryOptions, RepositoryStats, SearchCache, SearchMode, SearchQuery, StackMatch,
+    WriteBehindConfig, WriteFailure, LAST_ID_QUERY, PERSON_CHANGES_CHANNEL, READINESS_TIMEOUT,
+    RESYNC_QUERY, WARM_UP_POLL_INTERVAL,
+};
 use sqlx::{
     postgres::{PgListener, PgPoolOptions},
-    PgPool,
+    PgPool, QueryBuilder,
 };
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-pub enum PersistenceError {
-    UniqueViolation,
-    DatabaseError(Box<dyn Error + Send + Sync>),
+pub struct PostgresRepository {
+    pool: PgPool,
+    cache: Arc<PersonCache>,
+    nicks: Arc<NickFilter>,
+    searches: Arc<SearchCache>,
+    listener_status: Arc<ListenerStatus>,
+    write_behind: Option<WriteBehind>,
+    count_mode: CountMode,
+    cache_warm_up: CacheWarmUp,
 }
 
-impl From<sqlx::Error> for PersistenceError {
-    fn from(error: sqlx::Error) -> Self {
-        match error {
-            sqlx::Error::Database(err) if err.is_unique_violation() => {
-                PersistenceError::UniqueViolation
-            }
-            _ => PersistenceError::DatabaseError(Box::new(error)),
-        }
-    }
+/// Where people created in write-behind mode wait to be written in batches.
+struct WriteBehind {
+    pending: Arc<PendingPeople>,
+    sender: mpsc::UnboundedSender<WriteBehindCommand>,
 }
 
-type PersistenceResult<T> = Result<T, PersistenceError>;
-
-pub struct PostgresRepository {
-    pool: PgPool,
-    cache: Arc<DashMap<Uuid, Person>>,
-    nicks: Arc<DashSet<String>>,
+enum WriteBehindCommand {
+    Insert(Person),
+    Flush(oneshot::Sender<()>),
 }
 
 impl PostgresRepository {
-    pub async fn connect(url: &str, pool_size: u32) -> Result<Self, sqlx::Error> {
+    /// Sets up the pool without waiting for the database, which may still be starting. The
+    /// cache is warmed up and changes are listened to in the background, retrying until the
+    /// database is there, and readiness fails until then. Servers should not accept traffic
+    /// before [`PostgresRepository::wait_for_warm_up`] returns.
+    pub fn connect(
+        url: &str,
+        pool_size: u32,
+        options: RepositoryOptions,
+    ) -> Result<Self, sqlx::Error> {
         let pool = PgPoolOptions::new()
             .max_connections(pool_size)
-            .connect(url)
-            .await?;
+            .connect_lazy(url)?;
+
+        let cache = Arc::new(PersonCache::new(options.cache_limits));
+        let nicks = Arc::new(NickFilter::new(options.nick_filter));
+        let searches = Arc::new(SearchCache::new(options.search_cache));
 
-        let cache = Arc::new(DashMap::with_capacity(30_000));
-        let nicks = Arc::new(DashSet::new());
+        let listener_status = Arc::new(ListenerStatus::default());
 
         tokio::spawn({
             let pool = pool.clone();
             let cache = cache.clone();
             let nicks = nicks.clone();
+            let searches = searches.clone();
+            let listener_status = listener_status.clone();
+            let cache_warm_up = options.cache_warm_up;
             async move {
-                if let Ok(mut listener) = PgListener::connect_with(&pool).await {
-                    listener.listen("person_created").await.ok();
-                    while let Ok(msg) = listener.recv().await {
-                        if let Ok(person) = serde_json::from_str::<Person>(msg.payload()) {
-                            nicks.insert(person.nick.as_str().to_owned());
-                            cache.insert(person.id, person);
-                        }
+                let mut backoff = Backoff::default();
+                let mut catch_up = CatchUp::WarmUp(cache_warm_up);
+                loop {
+                    if let Err(err) = listen(
+                        &pool,
+                        &cache,
+                        &nicks,
+                        &searches,
+                        &listener_status,
+                        &mut backoff,
+                        catch_up,
+                    )
+                    .await
+                    {
+                        warn!(error = %err, "change listener disconnected");
                     }
+                    catch_up = catch_up.after_disconnect(listener_status.is_connected());
+                    listener_status.set_connected(false);
+                    tokio::time::sleep(backoff.next_delay()).await;
                 }
             }
         });
 
-        Ok(PostgresRepository { pool, cache, nicks })
+        let write_behind = options.write_behind.map(|config| {
+            let pending = Arc::new(PendingPeople::new());
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let (worker_pool, worker_pending) = (pool.clone(), pending.clone());
+            tokio::spawn(write_behind_worker(receiver, config, move |batch| {
+                let (pool, pending) = (worker_pool.clone(), worker_pending.clone());
+                async move { write_batch(&pool, &pending, batch).await }
+            }));
+            WriteBehind { pending, sender }
+        });
+
+        Ok(PostgresRepository {
+            pool,
+            cache,
+            nicks,
+            searches,
+            listener_status,
+            write_behind,
+            count_mode: options.count_mode,
+            cache_warm_up: options.cache_warm_up,
+        })
     }
+}
+
+/// Writes queued people in batches, whenever a batch fills up, the flush interval passes or a
+/// flush is requested, until every sender is gone.
+async fn write_behind_worker<F, Fut>(
+    mut receiver: mpsc::UnboundedReceiver<WriteBehindCommand>,
+    config: WriteBehindConfig,
+    mut write: F,
+) where
+    F: FnMut(Vec<Person>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let batch_size = config.batch_size();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut interval = tokio::time::interval(config.flush_interval);
+
+    loop {
+        tokio::select! {
+            command = receiver.recv() => match command {
+                Some(WriteBehindCommand::Insert(person)) => {
+                    batch.push(person);
+                    if batch.len() >= batch_size {
+                        write(std::mem::take(&mut batch)).await;
+                    }
+                }
+                Some(WriteBehindCommand::Flush(done)) => {
+                    write(std::mem::take(&mut batch)).await;
+                    done.send(()).ok();
+                }
+                None => {
+                    write(std::mem::take(&mut batch)).await;
+                    return;
+                }
+            },
+            _ = interval.tick() => write(std::mem::take(&mut batch)).await,
+        }
+    }
+}
+
+/// Writes a batch as [`BatchWrite`] tells, then forgets its people, dropping the reservations of
+/// those that could not be written so their nicks are free again.
+async fn write_batch(pool: &PgPool, pending: &PendingPeople, batch: Vec<Person>) {
+    let mut write = BatchWrite::new(batch);
+    while let Some(part) = write.next_part() {
+        let mut query =
+            QueryBuilder::new("INSERT INTO people (id, name, nick, birth_date, stack) ");
+        query.push_values(part, |mut row, person| {
+            row.push_bind(person.id)
+                .push_bind(person.name.as_str())
+                .push_bind(person.nick.as_str())
+                .push_bind(person.birth_date)
+                .push_bind(person.stack.as_deref());
+        });
+        query.push(" ON CONFLICT DO NOTHING RETURNING id");
+
+        let people = part.len();
+        match query.build_query_scalar::<Uuid>().fetch_all(pool).await {
+            Ok(written) => write.written(&written),
+            Err(err) => {
+                error!(error = %err, people, "write-behind: batch failed");
+                if let Some(delay) = write.failed(WriteFailure::from(&err)) {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    let outcome = write.finish();
+    if !outcome.dropped.is_empty() {
+        warn!(
+            dropped = outcome.dropped.len(),
+            "write-behind: people dropped"
+        );
+        release_nicks(pool, &outcome.dropped).await;
+    }
+    pending.release(outcome.settled.iter());
+}
+
+/// Frees the nicks reserved by people that were never written.
+async fn release_nicks(pool: &PgPool, people: &[Person]) {
+    let ids: Vec<Uuid> = people.iter().map(|person| person.id).collect();
+    if let Err(err) = sqlx::query("DELETE FROM person_nicks WHERE id = ANY($1)")
+        .bind(&ids)
+        .execute(pool)
+        .await
+    {
+        error!(error = %err, people = ids.len(), "write-behind: failed to release nicks");
+    }
+}
+
+/// Claims the nick of a person about to be written behind, failing if somebody already owns it.
+async fn reserve_nick(pool: &PgPool, person: &Person) -> PersistenceResult<()> {
+    let reserved = sqlx::query(
+        "INSERT INTO person_nicks (nick, id) VALUES ($1, $2) ON CONFLICT (nick) DO NOTHING",
+    )
+    .bind(person.nick.as_str())
+    .bind(person.id)
+    .execute(pool)
+    .await?;
+
+    match reserved.rows_affected() {
+        0 => Err(PersistenceError::UniqueViolation),
+        _ => Ok(()),
+    }
+}
 
-    pub async fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
-        if let Some(person) = self.cache.get(&id).map(|entry| entry.value().clone()) {
+/// Streams existing people into the caches, as [`CacheWarmUp::query`] tells.
+async fn warm_up(
+    pool: &PgPool,
+    cache: &PersonCache,
+    nicks: &NickFilter,
+    listener_status: &ListenerStatus,
+    cache_warm_up: CacheWarmUp,
+) -> Result<(), sqlx::Error> {
+    let Some((query, newest)) = cache_warm_up.query() else {
+        return Ok(());
+    };
+    let mut query = sqlx::query_as::<_, Person>(query);
+    if let Some(newest) = newest {
+        query = query.bind(newest);
+    }
+    let mut people = query.fetch(pool);
+
+    let mut loaded = 0;
+    while let Some(person) = people.try_next().await? {
+        CatchUp::load(person, cache, nicks, listener_status);
+
+        loaded += 1;
+        if loaded % 10_000 == 0 {
+            info!(loaded, "cache warm-up: people loaded");
+        }
+    }
+    info!(loaded, "cache warm-up: finished");
+
+    Ok(())
+}
+
+/// Applies person changes to the caches until the connection is lost, having caught up as
+/// [`CatchUp`] describes.
+async fn listen(
+    pool: &PgPool,
+    cache: &PersonCache,
+    nicks: &NickFilter,
+    searches: &SearchCache,
+    listener_status: &ListenerStatus,
+    backoff: &mut Backoff,
+    catch_up: CatchUp,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(PERSON_CHANGES_CHANNEL).await?;
+
+    cache.clear();
+    searches.clear();
+
+    match catch_up {
+        CatchUp::WarmUp(cache_warm_up) => {
+            warm_up(pool, cache, nicks, listener_status, cache_warm_up).await?;
+
+            let last_id: Option<Uuid> = sqlx::query_scalar(LAST_ID_QUERY)
+                .fetch_optional(pool)
+                .await?;
+            if let Some(last_id) = last_id {
+                listener_status.see_id(last_id);
+            }
+        }
+        CatchUp::Resync => {
+            let missed: Vec<Person> = sqlx::query_as(RESYNC_QUERY)
+                .bind(listener_status.last_id().unwrap_or_default())
+                .fetch_all(pool)
+                .await?;
+            for person in missed {
+                CatchUp::load(person, cache, nicks, listener_status);
+            }
+        }
+    }
+
+    listener_status.set_connected(true);
+    backoff.reset();
+
+    while let Some(msg) = listener.try_recv().await? {
+        match serde_json::from_str::<PersonChange>(msg.payload()) {
+            Ok(change) => {
+                listener_status.observe(&change);
+                change.apply(cache, nicks, searches);
+            }
+            Err(err) => warn!(error = %err, payload = msg.payload(), "unreadable person change"),
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl AsyncPersonRepository for PostgresRepository {
+    async fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
+        if let Some(person) = self.pending(&id) {
             return Ok(Some(person));
         }
 
+        if self.listener_status.is_connected() {
+            if let Some(person) = self.cache.get(&id) {
+                return Ok(Some(person));
+            }
+        }
+
         sqlx::query_as(
             "
             SELECT id, name, nick, birth_date, stack
@@ -80,14 +331,51 @@ impl PostgresRepository {
         .map_err(PersistenceError::from)
     }
 
-    pub async fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid> {
-        if self.nicks.contains(new_person.nick.as_str()) {
-            return Err(PersistenceError::UniqueViolation);
+    async fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid> {
+        if let Some(write_behind) = &self.write_behind {
+            if self.listener_status.is_connected()
+                && !self.nicks.may_contain(new_person.nick.as_str())
+            {
+                let person = new_person.into_person(Uuid::now_v7());
+                if !write_behind.pending.reserve(&person) {
+                    return Err(PersistenceError::UniqueViolation);
+                }
+                if let Err(err) = reserve_nick(&self.pool, &person).await {
+                    write_behind.pending.release([&person]);
+                    return Err(err);
+                }
+                self.nicks.insert(person.nick.as_str());
+
+                let id = person.id;
+                write_behind
+                    .sender
+                    .send(WriteBehindCommand::Insert(person))
+                    .map_err(|err| PersistenceError::DatabaseError(err.to_string().into()))?;
+                return Ok(id);
+            }
         }
 
-        let stack = new_person
-            .stack
-            .map(|stack| stack.into_iter().map(String::from).collect::<Vec<_>>());
+        let stack = new_person.stack.map(normalize_stack);
+
+        if self.nicks.may_contain(new_person.nick.as_str()) {
+            let id: Option<Uuid> = sqlx::query_scalar(
+                "
+                INSERT INTO people (id, name, nick, birth_date, stack)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (nick) DO NOTHING
+                RETURNING id
+                ",
+            )
+            .bind(Uuid::now_v7())
+            .bind(new_person.name.as_str())
+            .bind(new_person.nick.as_str())
+            .bind(new_person.birth_date)
+            .bind(stack)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            return id.ok_or(PersistenceError::UniqueViolation);
+        }
 
         sqlx::query!(
             "
@@ -107,27 +395,291 @@ impl PostgresRepository {
         .map_err(PersistenceError::from)
     }
 
-    pub async fn search_people(&self, query: &str) -> PersistenceResult<Vec<Person>> {
-        sqlx::query_as(
+    async fn update_person(
+        &self,
+        id: Uuid,
+        person: NewPerson,
+    ) -> PersistenceResult<Option<Person>> {
+        if self.pending(&id).is_some() {
+            self.flush().await?;
+        }
+
+        let person = sqlx::query_as(
             "
-            SELECT id, name, nick, birth_date, stack
-            FROM people
-            WHERE search ILIKE $1
-            LIMIT 50
+            UPDATE people
+            SET name = $2, nick = $3, birth_date = $4, stack = $5
+            WHERE id = $1
+            RETURNING id, name, nick, birth_date, stack
             ",
         )
-        .bind(format!("%{query}%"))
-        .fetch_all(&self.pool)
-        .await
-        .map_err(PersistenceError::from)
+        .bind(id)
+        .bind(person.name.as_str())
+        .bind(person.nick.as_str())
+        .bind(person.birth_date)
+        .bind(person.stack.map(normalize_stack))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // The cached copy is left for the change listener to refresh, as it applies changes in
+        // the order they were committed.
+        self.cache.remove(&id);
+
+        Ok(person)
     }
 
-    pub async fn count_people(&self) -> PersistenceResult<u64> {
-        sqlx::query!("SELECT COUNT(*) AS count FROM people")
+    async fn delete_person(&self, id: Uuid) -> PersistenceResult<bool> {
+        if self.pending(&id).is_some() {
+            self.flush().await?;
+        }
+
+        let deleted = sqlx::query("DELETE FROM people WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        self.cache.remove(&id);
+
+        Ok(deleted > 0)
+    }
+
+    async fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>> {
+        let connected = self.listener_status.is_connected();
+        if connected {
+            if let Some(people) = self.searches.get(query) {
+                return Ok(people);
+            }
+        }
+
+        let mut sql =
+            QueryBuilder::new("SELECT id, name, nick, birth_date, stack FROM people WHERE TRUE");
+
+        match (&query.term, query.mode) {
+            (None, _) => {}
+            (Some(term), SearchMode::Substring) => {
+                sql.push(" AND search ILIKE ")
+                    .push_bind(term.contains_pattern());
+            }
+            (Some(term), SearchMode::Trigram) => {
+                sql.push(" AND search % ").push_bind(term.as_str());
+            }
+            (Some(term), SearchMode::FullText) => {
+                sql.push(" AND search_document @@ PLAINTO_TSQUERY('portuguese', ")
+                    .push_bind(term.as_str())
+                    .push(")");
+            }
+        }
+        if let Some(name) = &query.name {
+            sql.push(" AND name ILIKE ")
+                .push_bind(name.contains_pattern());
+        }
+        if let Some(nick) = &query.nick {
+            sql.push(" AND nick = ").push_bind(nick);
+        }
+        if !query.stack.is_empty() {
+            sql.push(match query.stack_match {
+                StackMatch::Any => " AND LOWER_ARRAY_IMMUTABLE(stack) && ",
+                StackMatch::All => " AND LOWER_ARRAY_IMMUTABLE(stack) @> ",
+            })
+            .push_bind(&query.stack);
+        }
+        if let Some(from) = query.born_from {
+            sql.push(" AND birth_date >= ").push_bind(from);
+        }
+        if let Some(until) = query.born_until {
+            sql.push(" AND birth_date <= ").push_bind(until);
+        }
+
+        match (&query.term, query.mode) {
+            (Some(term), SearchMode::Trigram) => {
+                sql.push(" ORDER BY SIMILARITY(search, ")
+                    .push_bind(term.as_str())
+                    .push(") DESC");
+            }
+            (Some(term), SearchMode::FullText) => {
+                sql.push(" ORDER BY TS_RANK(search_document, PLAINTO_TSQUERY('portuguese', ")
+                    .push_bind(term.as_str())
+                    .push(")) DESC");
+            }
+            _ => {
+                if let Some(cursor) = query.cursor {
+                    sql.push(" AND id > ").push_bind(cursor);
+                }
+                sql.push(" ORDER BY id");
+            }
+        }
+        sql.push(" LIMIT ").push_bind(query.limit() as i64);
+
+        let people: Vec<Person> = sql.build_query_as().fetch_all(&self.pool).await?;
+
+        if connected {
+            self.searches.insert(query, people.clone());
+        }
+
+        Ok(people)
+    }
+
+    async fn count_people(&self) -> PersistenceResult<u64> {
+        sqlx::query_scalar::<_, i64>(self.count_mode.query())
             .fetch_one(&self.pool)
             .await
-            .map(|row| row.count.unwrap_or_default())
-            .map(|count| count.unsigned_abs())
+            .map(CountMode::people)
             .map_err(PersistenceError::from)
     }
+
+    async fn flush(&self) -> PersistenceResult<()> {
+        let Some(write_behind) = &self.write_behind else {
+            return Ok(());
+        };
+
+        let (done, flushed) = oneshot::channel();
+        write_behind
+            .sender
+            .send(WriteBehindCommand::Flush(done))
+            .map_err(|err| PersistenceError::DatabaseError(err.to_string().into()))?;
+        flushed
+            .await
+            .map_err(|err| PersistenceError::DatabaseError(err.into()))
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats {
+            pool: Some(PoolStats {
+                size: self.pool.size(),
+                idle: self.pool.num_idle() as u32,
+                max_size: self.pool.options().get_max_connections(),
+            }),
+            cache: Some(self.cache.stats()),
+            searches: Some(self.searches.stats()),
+            nicks: Some(self.nicks.stats()),
+            listener: Some(self.listener_status.stats()),
+        }
+    }
+
+    async fn readiness(&self) -> Readiness {
+        let database = within_timeout(sqlx::query("SELECT 1").execute(&self.pool))
+            .await
+            .map(|_| ());
+        let migrations = within_timeout(
+            sqlx::query_scalar::<_, Option<i32>>("SELECT MAX(version) FROM schema_version")
+                .fetch_one(&self.pool),
+        )
+        .await
+        .and_then(check_schema_version);
+        let listener = match self.listener_status.is_connected() {
+            true => HealthCheck::passed(),
+            false => HealthCheck::failed("not listening to person changes"),
+        };
+
+        Readiness {
+            database: Some(database.into()),
+            migrations: Some(migrations.into()),
+            listener: Some(listener),
+        }
+    }
+}
+
+async fn within_timeout<T>(
+    query: impl Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, String> {
+    match tokio::time::timeout(READINESS_TIMEOUT, query).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(_) => Err(String::from("timed out")),
+    }
+}
+
+impl PostgresRepository {
+    /// Waits until the caches are warmed up, which they are once changes are first listened to.
+    /// Returns right away when they are not warmed up at all.
+    pub async fn wait_for_warm_up(&self) {
+        if self.cache_warm_up == CacheWarmUp::Disabled {
+            return;
+        }
+        while !self.listener_status.is_connected() {
+            tokio::time::sleep(WARM_UP_POLL_INTERVAL).await;
+        }
+    }
+
+    fn pending(&self, id: &Uuid) -> Option<Person> {
+        self.write_behind
+            .as_ref()
+            .and_then(|write_behind| write_behind.pending.get(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use rinha_core::{Nick, PersonName};
+    use time::macros::date;
+
+    use super::*;
+
+    fn person(n: u64) -> Person {
+        Person {
+            id: Uuid::from_u64_pair(0, n),
+            name: PersonName::from_stored(format!("Person {n}")),
+            nick: Nick::from_stored(format!("nick{n}")),
+            birth_date: date!(2000 - 01 - 01),
+            stack: None,
+        }
+    }
+
+    /// Starts a worker that only records the batches it is given.
+    fn worker(
+        batch_size: usize,
+    ) -> (
+        mpsc::UnboundedSender<WriteBehindCommand>,
+        Arc<Mutex<Vec<usize>>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let config = WriteBehindConfig {
+            batch_size,
+            flush_interval: Duration::from_secs(3600),
+        };
+        let batches = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let written = batches.clone();
+        let handle = tokio::spawn(write_behind_worker(receiver, config, move |batch| {
+            if !batch.is_empty() {
+                written.lock().unwrap().push(batch.len());
+            }
+            async {}
+        }));
+        (sender, batches, handle)
+    }
+
+    #[tokio::test]
+    async fn full_batches_are_written_right_away() {
+        let (sender, batches, _handle) = worker(2);
+        for n in 0..2 {
+            sender.send(WriteBehindCommand::Insert(person(n))).unwrap();
+        }
+        let (done, flushed) = oneshot::channel();
+        sender.send(WriteBehindCommand::Flush(done)).unwrap();
+        flushed.await.unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn a_flush_returns_once_everything_queued_is_written() {
+        let (sender, batches, _handle) = worker(10);
+        for n in 0..3 {
+            sender.send(WriteBehindCommand::Insert(person(n))).unwrap();
+        }
+        let (done, flushed) = oneshot::channel();
+        sender.send(WriteBehindCommand::Flush(done)).unwrap();
+        flushed.await.unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn the_rest_is_written_when_the_senders_are_gone() {
+        let (sender, batches, handle) = worker(10);
+        sender.send(WriteBehindCommand::Insert(person(0))).unwrap();
+        drop(sender);
+        handle.await.unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![1]);
+    }
 }
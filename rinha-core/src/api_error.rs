@@ -0,0 +1,217 @@
+use serde::Serialize;
+
+use crate::{PayloadError, PersistenceError, ValidationError, ValidationRule};
+
+/// What went wrong with a request, as told to clients.
+///
+/// Both servers answer every failed request with one of these, rendered as
+/// `{"erro": {"codigo": ..., "mensagem": ..., "campo": ...}}`. Codes are stable and meant for
+/// programs to branch on, while messages are for people and may change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiError {
+    #[serde(rename = "codigo")]
+    pub code: ErrorCode,
+    #[serde(rename = "mensagem")]
+    pub message: String,
+    #[serde(rename = "campo", skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// The broken rule of an invalid field, along with its limit.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<ValidationRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorCode {
+    /// The body is not JSON or some field has the wrong type.
+    #[serde(rename = "requisicao_invalida")]
+    MalformedRequest,
+    /// A query string parameter could not be parsed.
+    #[serde(rename = "parametro_invalido")]
+    InvalidParameter,
+    /// A search without a term or filters, while listing everybody is not allowed.
+    #[serde(rename = "busca_sem_criterios")]
+    MissingSearchCriteria,
+    /// A field of the body breaks a rule.
+    #[serde(rename = "campo_invalido")]
+    InvalidField,
+    #[serde(rename = "apelido_duplicado")]
+    DuplicateNick,
+    #[serde(rename = "nao_encontrado")]
+    NotFound,
+    /// The path exists, but doesn't take the method of the request.
+    #[serde(rename = "metodo_nao_permitido")]
+    MethodNotAllowed,
+    /// The database failed or could not be reached, so the request may be retried.
+    #[serde(rename = "banco_indisponivel")]
+    DatabaseUnavailable,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    #[serde(rename = "erro")]
+    error: &'a ApiError,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            field: None,
+            rule: None,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(ErrorCode::NotFound, "person not found")
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self::new(
+            ErrorCode::MethodNotAllowed,
+            "the route doesn't take this method",
+        )
+    }
+
+    pub fn missing_search_criteria() -> Self {
+        Self::new(
+            ErrorCode::MissingSearchCriteria,
+            "a search term or filter is required",
+        )
+    }
+
+    pub fn status(&self) -> u16 {
+        match self.code {
+            ErrorCode::MalformedRequest
+            | ErrorCode::InvalidParameter
+            | ErrorCode::MissingSearchCriteria => 400,
+            ErrorCode::NotFound => 404,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::InvalidField | ErrorCode::DuplicateNick => 422,
+            ErrorCode::DatabaseUnavailable => 503,
+        }
+    }
+
+    /// The response body, wrapped in its `erro` object.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(&ErrorBody { error: self }).unwrap()
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(err: ValidationError) -> Self {
+        Self {
+            code: ErrorCode::InvalidField,
+            message: err.to_string(),
+            field: Some(err.field),
+            rule: Some(err.rule),
+        }
+    }
+}
+
+impl From<PayloadError> for ApiError {
+    fn from(err: PayloadError) -> Self {
+        match err {
+            PayloadError::Syntax(err) => Self::new(ErrorCode::MalformedRequest, err.to_string()),
+            PayloadError::Invalid(err) => err.into(),
+        }
+    }
+}
+
+/// Database failures are not detailed to clients, so servers should log them before converting.
+impl From<PersistenceError> for ApiError {
+    fn from(err: PersistenceError) -> Self {
+        match err {
+            PersistenceError::UniqueViolation => Self {
+                field: Some(String::from("apelido")),
+                ..Self::new(ErrorCode::DuplicateNick, "apelido is already taken")
+            },
+            PersistenceError::DatabaseError(_) => Self::new(
+                ErrorCode::DatabaseUnavailable,
+                "the database is unavailable",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn body(err: &ApiError) -> Value {
+        serde_json::from_slice(&err.to_json()).unwrap()
+    }
+
+    #[test]
+    fn every_code_has_its_name_and_status() {
+        let codes = [
+            (ErrorCode::MalformedRequest, "requisicao_invalida", 400),
+            (ErrorCode::InvalidParameter, "parametro_invalido", 400),
+            (ErrorCode::MissingSearchCriteria, "busca_sem_criterios", 400),
+            (ErrorCode::InvalidField, "campo_invalido", 422),
+            (ErrorCode::DuplicateNick, "apelido_duplicado", 422),
+            (ErrorCode::NotFound, "nao_encontrado", 404),
+            (ErrorCode::MethodNotAllowed, "metodo_nao_permitido", 405),
+            (ErrorCode::DatabaseUnavailable, "banco_indisponivel", 503),
+        ];
+        for (code, name, status) in codes {
+            let err = ApiError::new(code, "message");
+            assert_eq!(err.status(), status, "{name}");
+            assert_eq!(
+                body(&err),
+                json!({"erro": {"codigo": name, "mensagem": "message"}})
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_fields_are_named_along_with_their_rule() {
+        let err = ApiError::from(ValidationError {
+            field: String::from("nascimento"),
+            rule: ValidationRule::MinYear { min: 1900 },
+        });
+        assert_eq!(err.status(), 422);
+        assert_eq!(
+            body(&err),
+            json!({"erro": {
+                "codigo": "campo_invalido",
+                "mensagem": err.message,
+                "campo": "nascimento",
+                "regra": "ano_minimo",
+                "minimo": 1900,
+            }})
+        );
+    }
+
+    #[test]
+    fn malformed_payloads_are_bad_requests() {
+        let syntax = serde_json::from_str::<Value>("{").unwrap_err();
+        let err = ApiError::from(PayloadError::Syntax(syntax));
+        assert_eq!(err.code, ErrorCode::MalformedRequest);
+        assert_eq!(err.status(), 400);
+        assert_eq!(err.field, None);
+    }
+
+    #[test]
+    fn duplicate_nicks_point_at_the_nick() {
+        let err = ApiError::from(PersistenceError::UniqueViolation);
+        assert_eq!(err.status(), 422);
+        assert_eq!(body(&err)["erro"]["codigo"], "apelido_duplicado");
+        assert_eq!(body(&err)["erro"]["campo"], "apelido");
+    }
+
+    #[test]
+    fn database_failures_are_not_detailed() {
+        let err = ApiError::from(PersistenceError::DatabaseError("connection refused".into()));
+        assert_eq!(err.status(), 503);
+        assert_eq!(
+            body(&err),
+            json!({"erro": {
+                "codigo": "banco_indisponivel",
+                "mensagem": "the database is unavailable",
+            }})
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::{env, sync::OnceLock};
+
+use time::{Date, OffsetDateTime};
+
+use crate::{ValidationError, ValidationRule};
+
+static RULES: OnceLock<BirthDateRules> = OnceLock::new();
+
+/// Constraints applied to `nascimento` whenever a [`NewPerson`](crate::NewPerson) is validated.
+#[derive(Debug, Clone)]
+pub struct BirthDateRules {
+    pub allow_future: bool,
+    pub min_year: i32,
+    pub min_age: Option<u8>,
+}
+
+impl Default for BirthDateRules {
+    fn default() -> Self {
+        Self {
+            allow_future: false,
+            min_year: 1900,
+            min_age: None,
+        }
+    }
+}
+
+impl BirthDateRules {
+    /// Makes these the rules used by every validation in the process. This should happen at
+    /// startup, as it fails, giving the rules back, once any were installed or used.
+    pub fn install(self) -> Result<(), Self> {
+        RULES.set(self)
+    }
+
+    pub fn current() -> &'static Self {
+        RULES.get_or_init(Self::default)
+    }
+
+    /// Reads the rules from environment variables, leaving the defaults for those unset or
+    /// unparseable.
+    pub fn from_env() -> Self {
+        Self {
+            allow_future: env::var("BIRTH_DATE_ALLOW_FUTURE").is_ok_and(|allow| allow == "true"),
+            min_year: env::var("BIRTH_DATE_MIN_YEAR")
+                .ok()
+                .and_then(|year| year.parse::<i32>().ok())
+                .unwrap_or(Self::default().min_year),
+            min_age: env::var("BIRTH_DATE_MIN_AGE")
+                .ok()
+                .and_then(|age| age.parse::<u8>().ok()),
+        }
+    }
+
+    pub fn validate(&self, field: &str, date: Date) -> Result<(), ValidationError> {
+        self.validate_on(field, date, OffsetDateTime::now_utc().date())
+    }
+
+    fn validate_on(&self, field: &str, date: Date, today: Date) -> Result<(), ValidationError> {
+        if !self.allow_future && date > today {
+            return Err(ValidationError::new(field, ValidationRule::FutureDate));
+        }
+
+        if date.year() < self.min_year {
+            return Err(ValidationError::new(
+                field,
+                ValidationRule::MinYear { min: self.min_year },
+            ));
+        }
+
+        match self.min_age {
+            Some(min_age) if age(date, today) < i32::from(min_age) => Err(ValidationError::new(
+                field,
+                ValidationRule::MinAge { min: min_age },
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn age(birth_date: Date, today: Date) -> i32 {
+    let had_birthday =
+        (today.month() as u8, today.day()) >= (birth_date.month() as u8, birth_date.day());
+    today.year() - birth_date.year() - if had_birthday { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+
+    const TODAY: Date = date!(2024 - 03 - 01);
+
+    fn rule(rules: &BirthDateRules, date: Date, today: Date) -> Option<ValidationRule> {
+        rules
+            .validate_on("nascimento", date, today)
+            .err()
+            .map(|err| err.rule)
+    }
+
+    #[test]
+    fn future_dates_are_rejected_unless_allowed() {
+        let rules = BirthDateRules::default();
+        assert_eq!(rule(&rules, TODAY, TODAY), None);
+        assert_eq!(
+            rule(&rules, date!(2024 - 03 - 02), TODAY),
+            Some(ValidationRule::FutureDate)
+        );
+
+        let rules = BirthDateRules {
+            allow_future: true,
+            ..BirthDateRules::default()
+        };
+        assert_eq!(rule(&rules, date!(2024 - 03 - 02), TODAY), None);
+    }
+
+    #[test]
+    fn min_year_is_inclusive() {
+        let rules = BirthDateRules::default();
+        assert_eq!(rule(&rules, date!(1900 - 01 - 01), TODAY), None);
+        assert_eq!(
+            rule(&rules, date!(1899 - 12 - 31), TODAY),
+            Some(ValidationRule::MinYear { min: 1900 })
+        );
+    }
+
+    #[test]
+    fn min_age_is_reached_on_the_birthday() {
+        let rules = BirthDateRules {
+            min_age: Some(18),
+            ..BirthDateRules::default()
+        };
+        assert_eq!(rule(&rules, date!(2006 - 03 - 01), TODAY), None);
+        assert_eq!(
+            rule(&rules, date!(2006 - 03 - 02), TODAY),
+            Some(ValidationRule::MinAge { min: 18 })
+        );
+    }
+
+    #[test]
+    fn leap_day_birthdays_are_reached_on_the_first_of_march() {
+        let rules = BirthDateRules {
+            min_age: Some(18),
+            ..BirthDateRules::default()
+        };
+        assert_eq!(
+            rule(&rules, date!(2004 - 02 - 29), date!(2022 - 02 - 28)),
+            Some(ValidationRule::MinAge { min: 18 })
+        );
+        assert_eq!(
+            rule(&rules, date!(2004 - 02 - 29), date!(2022 - 03 - 01)),
+            None
+        );
+        assert_eq!(
+            rule(&rules, date!(2004 - 02 - 29), date!(2024 - 02 - 29)),
+            None
+        );
+    }
+
+    #[test]
+    fn rules_can_only_be_installed_once() {
+        BirthDateRules::default().install().ok();
+        assert!(BirthDateRules::default().install().is_err());
+    }
+}
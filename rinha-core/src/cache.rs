@@ -0,0 +1,399 @@
+use std::{
+    mem,
+    num::{NonZeroUsize, ParseIntError},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use lru::LruCache;
+use uuid::Uuid;
+
+use crate::Person;
+
+const SHARDS: usize = 16;
+
+/// How many deleted people are remembered, so stale changes can't bring them back.
+const MAX_TOMBSTONES: usize = 10_000;
+
+/// Upper bounds for a [`PersonCache`]. Each one is split evenly between the cache shards.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheLimits {
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: Some(30_000),
+            max_bytes: None,
+        }
+    }
+}
+
+/// How many existing people are loaded into the caches before an instance starts serving.
+///
+/// Nothing routes on readiness, so servers wait for the warm-up before taking traffic at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheWarmUp {
+    #[default]
+    Disabled,
+    All,
+    /// Only the most recently created ones.
+    Newest(u64),
+}
+
+impl FromStr for CacheWarmUp {
+    type Err = ParseIntError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "" | "off" => Ok(Self::Disabled),
+            "all" => Ok(Self::All),
+            count => count.parse().map(Self::Newest),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// People cached by id, evicting the least recently used ones once over its limits.
+///
+/// Entries are spread over independently locked shards, so concurrent requests rarely wait on
+/// each other, at the cost of the recency order being kept per shard.
+///
+/// Each entry remembers the `seq` of the last change applied to it, and so do the most recently
+/// deleted people, to tell stale changes apart. Those about anybody else are always applied.
+pub struct PersonCache {
+    shards: Vec<Mutex<Shard>>,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+struct Shard {
+    entries: LruCache<Uuid, Entry>,
+    /// The `seq` of the deletion of each recently deleted person.
+    tombstones: LruCache<Uuid, u64>,
+    bytes: usize,
+}
+
+struct Entry {
+    person: Person,
+    seq: u64,
+    size: usize,
+}
+
+impl PersonCache {
+    pub fn new(limits: CacheLimits) -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        entries: LruCache::unbounded(),
+                        tombstones: LruCache::new(
+                            NonZeroUsize::new(MAX_TOMBSTONES.div_ceil(SHARDS)).unwrap(),
+                        ),
+                        bytes: 0,
+                    })
+                })
+                .collect(),
+            max_entries: limits.max_entries.map(|max| max.div_ceil(SHARDS)),
+            max_bytes: limits.max_bytes.map(|max| max.div_ceil(SHARDS)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, id: &Uuid) -> &Mutex<Shard> {
+        let (_, random_bits) = id.as_u64_pair();
+        &self.shards[random_bits as usize % SHARDS]
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Person> {
+        let mut shard = self.shard(id).lock().unwrap();
+        match shard.entries.get(id) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.person.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Caches a person loaded from the database, whose last change is unknown.
+    pub fn insert(&self, person: Person) {
+        self.insert_at(person, 0);
+    }
+
+    /// Whether a change on a person is older than the last one applied to them, and so must be
+    /// dropped.
+    pub fn is_stale(&self, id: &Uuid, seq: u64) -> bool {
+        let shard = self.shard(id).lock().unwrap();
+        let applied = shard
+            .entries
+            .peek(id)
+            .map(|entry| entry.seq)
+            .or_else(|| shard.tombstones.peek(id).copied());
+        applied.is_some_and(|applied| applied >= seq)
+    }
+
+    /// Caches a person as of the change numbered `seq`.
+    pub fn insert_at(&self, person: Person, seq: u64) {
+        let size = estimated_size(&person);
+        let mut shard = self.shard(&person.id).lock().unwrap();
+
+        let id = person.id;
+        if let Some(previous) = shard.entries.put(id, Entry { person, seq, size }) {
+            shard.bytes -= previous.size;
+        }
+        shard.bytes += size;
+
+        while self.is_over_limits(&shard) {
+            match shard.entries.pop_lru() {
+                Some((_, entry)) => {
+                    shard.bytes -= entry.size;
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn remove(&self, id: &Uuid) {
+        let mut shard = self.shard(id).lock().unwrap();
+        if let Some(entry) = shard.entries.pop(id) {
+            shard.bytes -= entry.size;
+        }
+    }
+
+    /// Removes a person deleted by the change numbered `seq`, remembering it for a while.
+    pub fn remove_at(&self, id: &Uuid, seq: u64) {
+        let mut shard = self.shard(id).lock().unwrap();
+        if let Some(entry) = shard.entries.pop(id) {
+            shard.bytes -= entry.size;
+        }
+        shard.tombstones.put(*id, seq);
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.entries.clear();
+            shard.bytes = 0;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.entries += shard.entries.len();
+            stats.bytes += shard.bytes;
+        }
+        stats
+    }
+
+    fn is_over_limits(&self, shard: &Shard) -> bool {
+        self.max_entries
+            .is_some_and(|max| shard.entries.len() > max)
+            || self.max_bytes.is_some_and(|max| shard.bytes > max)
+    }
+}
+
+/// Rough heap and inline footprint of a cached person.
+fn estimated_size(person: &Person) -> usize {
+    mem::size_of::<(Uuid, Person, usize)>()
+        + person.name.as_str().len()
+        + person.nick.as_str().len()
+        + person.stack.as_ref().map_or(0, |stack| {
+            stack
+                .iter()
+                .map(|tech| mem::size_of::<String>() + tech.len())
+                .sum()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+    use crate::{
+        Nick, NickFilter, NickFilterConfig, PersonChange, PersonName, SearchCache,
+        SearchCacheConfig,
+    };
+
+    /// Somebody cached in the given shard, as ids are spread by their lower bits.
+    fn person(n: u64, shard: u64, name: &str) -> Person {
+        Person {
+            id: Uuid::from_u64_pair(n, n * SHARDS as u64 + shard),
+            name: PersonName::from_stored(name.to_owned()),
+            nick: Nick::from_stored(format!("nick{n}")),
+            birth_date: date!(2000 - 01 - 01),
+            stack: Some(vec![String::from("Rust")]),
+        }
+    }
+
+    fn limits(max_entries: Option<usize>, max_bytes: Option<usize>) -> CacheLimits {
+        CacheLimits {
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    #[test]
+    fn entry_limits_are_split_between_shards() {
+        // One entry per shard.
+        let cache = PersonCache::new(limits(Some(SHARDS), None));
+        let (a, b, c) = (person(1, 0, "a"), person(2, 1, "b"), person(3, 0, "c"));
+
+        cache.insert(a.clone());
+        cache.insert(b.clone());
+        assert_eq!(cache.stats().entries, 2);
+
+        cache.insert(c.clone());
+        assert!(cache.get(&a.id).is_none());
+        assert!(cache.get(&b.id).is_some());
+        assert!(cache.get(&c.id).is_some());
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        // Rounded up to two entries per shard.
+        let cache = PersonCache::new(limits(Some(SHARDS + 1), None));
+        let (a, b, c) = (person(1, 0, "a"), person(2, 0, "b"), person(3, 0, "c"));
+
+        cache.insert(a.clone());
+        cache.insert(b.clone());
+        cache.get(&a.id);
+        cache.insert(c.clone());
+
+        assert!(cache.get(&a.id).is_some());
+        assert!(cache.get(&b.id).is_none());
+        assert!(cache.get(&c.id).is_some());
+    }
+
+    #[test]
+    fn byte_limits_are_split_between_shards() {
+        let a = person(1, 0, "a");
+        let size = estimated_size(&a);
+        let cache = PersonCache::new(limits(None, Some(size * SHARDS)));
+
+        cache.insert(a.clone());
+        cache.insert(person(2, 1, "b"));
+        assert_eq!(cache.stats().entries, 2);
+
+        let c = person(3, 0, "c");
+        cache.insert(c.clone());
+        assert!(cache.get(&a.id).is_none());
+        assert!(cache.get(&c.id).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+
+        // Too big for a shard on its own.
+        let big = person(4, 2, "João Conceição");
+        cache.insert(big.clone());
+        assert!(cache.get(&big.id).is_none());
+        assert_eq!(cache.stats().evictions, 2);
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn bytes_follow_replaced_and_removed_entries() {
+        let cache = PersonCache::new(CacheLimits::default());
+        let short = person(1, 0, "a");
+        let long = person(1, 0, "João Conceição");
+        let other = person(2, 0, "b");
+
+        cache.insert(short.clone());
+        cache.insert(other.clone());
+        assert_eq!(
+            cache.stats().bytes,
+            estimated_size(&short) + estimated_size(&other)
+        );
+
+        cache.insert(long.clone());
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(
+            cache.stats().bytes,
+            estimated_size(&long) + estimated_size(&other)
+        );
+
+        cache.remove(&long.id);
+        assert_eq!(cache.stats().bytes, estimated_size(&other));
+        cache.remove_at(&other.id, 1);
+        assert_eq!(cache.stats().bytes, 0);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn lookups_are_counted() {
+        let cache = PersonCache::new(CacheLimits::default());
+        let a = person(1, 0, "a");
+
+        cache.get(&a.id);
+        cache.insert(a.clone());
+        cache.get(&a.id);
+        cache.get(&a.id);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 1, 0));
+    }
+
+    const ID: &str = "018a5b7e-7f4c-7000-8000-000000000001";
+
+    fn change(seq: u64, tipo: &str) -> PersonChange {
+        let json = match tipo {
+            "deleted" => {
+                format!(r#"{{"seq": {seq}, "tipo": "deleted", "id": "{ID}", "apelido": "joao"}}"#)
+            }
+            _ => format!(
+                r#"{{"seq": {seq}, "tipo": "{tipo}", "apelido_anterior": "joao", "pessoa": {{"id": "{ID}", "nome": "João {seq}", "apelido": "joao", "nascimento": "2000-01-01", "stack": null}}}}"#
+            ),
+        };
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn changes_older_than_the_last_applied_are_stale() {
+        let cache = PersonCache::new(CacheLimits::default());
+        let nicks = NickFilter::new(NickFilterConfig::default());
+        let searches = SearchCache::new(SearchCacheConfig::default());
+        let id = Uuid::parse_str(ID).unwrap();
+        let name =
+            |cache: &PersonCache| cache.get(&id).map(|person| person.name.as_str().to_owned());
+
+        change(1, "created").apply(&cache, &nicks, &searches);
+        change(3, "updated").apply(&cache, &nicks, &searches);
+        change(2, "updated").apply(&cache, &nicks, &searches);
+        assert_eq!(name(&cache).as_deref(), Some("João 3"));
+        assert!(cache.is_stale(&id, 3));
+
+        change(4, "deleted").apply(&cache, &nicks, &searches);
+        change(2, "created").apply(&cache, &nicks, &searches);
+        assert_eq!(name(&cache), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use crate::{CacheWarmUp, ListenerStatus, NickFilter, Person, PersonCache};
+
+/// How often servers check whether the caches are warm before they start serving.
+pub const WARM_UP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The newest person, from whom changes are caught up on after a reconnection.
+pub const LAST_ID_QUERY: &str = "SELECT id FROM people ORDER BY id DESC LIMIT 1";
+
+/// People created after the id bound to `$1`, missed while disconnected.
+pub const RESYNC_QUERY: &str = "
+    SELECT id, name, nick, birth_date, stack
+    FROM people
+    WHERE id > $1
+    ORDER BY id
+";
+
+const WARM_UP_ALL_QUERY: &str = "
+    SELECT id, name, nick, birth_date, stack
+    FROM people
+    ORDER BY id
+";
+
+const WARM_UP_NEWEST_QUERY: &str = "
+    SELECT * FROM (
+        SELECT id, name, nick, birth_date, stack
+        FROM people
+        ORDER BY id DESC
+        LIMIT $1
+    ) AS newest
+    ORDER BY id
+";
+
+/// How the caches are brought up to date once changes are being listened to.
+///
+/// Changes are listened to before the caches catch up, so none committed in the meantime is
+/// missed. Notifications sent while disconnected are gone for good, so after a reconnection cached
+/// people and searches are dropped, as they may be updated or deleted, and people created in the
+/// meantime are loaded from the database. The nick filter is kept, since a nick it still holds
+/// only costs a trip to the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    /// On the first connection, by warming them up as configured.
+    WarmUp(CacheWarmUp),
+    /// After a reconnection, by loading whatever was missed.
+    Resync,
+}
+
+impl CatchUp {
+    /// How to catch up after the connection is lost. Until the caches caught up once, warming
+    /// them up is started over.
+    pub fn after_disconnect(self, caught_up: bool) -> Self {
+        match caught_up {
+            true => CatchUp::Resync,
+            false => self,
+        }
+    }
+
+    /// Loads a person read while catching up into the caches.
+    pub fn load(
+        person: Person,
+        cache: &PersonCache,
+        nicks: &NickFilter,
+        listener_status: &ListenerStatus,
+    ) {
+        listener_status.see_id(person.id);
+        nicks.insert(person.nick.as_str());
+        cache.insert(person);
+    }
+}
+
+impl CacheWarmUp {
+    /// The query streaming existing people into the caches, oldest first so the newest ones are
+    /// the last to be evicted, along with the number of people to bind to `$1` when it takes one.
+    /// `None` when there is no warm-up.
+    pub fn query(self) -> Option<(&'static str, Option<i64>)> {
+        match self {
+            CacheWarmUp::Disabled => None,
+            CacheWarmUp::All => Some((WARM_UP_ALL_QUERY, None)),
+            CacheWarmUp::Newest(count) => Some((
+                WARM_UP_NEWEST_QUERY,
+                Some(i64::try_from(count).unwrap_or(i64::MAX)),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warming_up_is_started_over_until_it_finished_once() {
+        let warm_up = CatchUp::WarmUp(CacheWarmUp::All);
+        assert_eq!(warm_up.after_disconnect(false), warm_up);
+        assert_eq!(warm_up.after_disconnect(true), CatchUp::Resync);
+        assert_eq!(CatchUp::Resync.after_disconnect(false), CatchUp::Resync);
+    }
+
+    #[test]
+    fn only_the_newest_warm_up_takes_a_limit() {
+        assert_eq!(CacheWarmUp::Disabled.query(), None);
+        assert_eq!(CacheWarmUp::All.query().map(|(_, limit)| limit), Some(None));
+        assert_eq!(
+            CacheWarmUp::Newest(10).query().map(|(_, limit)| limit),
+            Some(Some(10))
+        );
+        assert_eq!(
+            CacheWarmUp::Newest(u64::MAX)
+                .query()
+                .map(|(_, limit)| limit),
+            Some(Some(i64::MAX))
+        );
+    }
+}
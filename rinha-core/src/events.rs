@@ -0,0 +1,120 @@
+use serde::{Deserialize, Deserializer};
+use time::Date;
+use uuid::Uuid;
+
+use crate::{Nick, NickFilter, Person, PersonCache, PersonName, SearchCache};
+
+/// Name of the Postgres channel every change on `people` is notified on.
+pub const PERSON_CHANGES_CHANNEL: &str = "person_changes";
+
+/// A change on `people`, as notified by the `notify_person_changed` trigger.
+///
+/// `seq` comes from a database sequence, so it always grows. Notifications are delivered in commit
+/// order, which may differ from `seq` order between people, so `seq` is only compared between
+/// changes on the same person, to drop any that is older than the last one applied. Only cached
+/// and recently deleted people remember it.
+#[derive(Deserialize)]
+pub struct PersonChange {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub kind: PersonChangeKind,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "tipo", rename_all = "snake_case")]
+pub enum PersonChangeKind {
+    Created {
+        #[serde(rename = "pessoa", deserialize_with = "stored_person")]
+        person: Person,
+    },
+    Updated {
+        #[serde(rename = "pessoa", deserialize_with = "stored_person")]
+        person: Person,
+        #[serde(rename = "apelido_anterior")]
+        previous_nick: String,
+    },
+    Deleted {
+        id: Uuid,
+        #[serde(rename = "apelido")]
+        nick: String,
+    },
+}
+
+/// A person as written by the trigger, straight from its row. It is not validated again, like any
+/// other row read back, or a change on somebody stored under older rules would be lost.
+#[derive(Deserialize)]
+struct StoredPerson {
+    id: Uuid,
+    #[serde(rename = "nome")]
+    name: String,
+    #[serde(rename = "apelido")]
+    nick: String,
+    #[serde(rename = "nascimento", with = "crate::date_format")]
+    birth_date: Date,
+    stack: Option<Vec<String>>,
+}
+
+fn stored_person<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Person, D::Error> {
+    let stored = StoredPerson::deserialize(deserializer)?;
+    Ok(Person {
+        id: stored.id,
+        name: PersonName::from_stored(stored.name),
+        nick: Nick::from_stored(stored.nick),
+        birth_date: stored.birth_date,
+        stack: stored.stack,
+    })
+}
+
+impl PersonChange {
+    pub fn person_id(&self) -> Uuid {
+        match &self.kind {
+            PersonChangeKind::Created { person } | PersonChangeKind::Updated { person, .. } => {
+                person.id
+            }
+            PersonChangeKind::Deleted { id, .. } => *id,
+        }
+    }
+
+    /// Applies the change to the caches, unless a later one on the same person already was.
+    pub fn apply(self, cache: &PersonCache, nicks: &NickFilter, searches: &SearchCache) {
+        if cache.is_stale(&self.person_id(), self.seq) {
+            return;
+        }
+
+        match self.kind {
+            PersonChangeKind::Created { person } => {
+                nicks.insert(person.nick.as_str());
+                searches.include(&person);
+                cache.insert_at(person, self.seq);
+            }
+            PersonChangeKind::Updated { person, .. } => {
+                nicks.insert(person.nick.as_str());
+                searches.invalidate_updated(&person);
+                cache.insert_at(person, self.seq);
+            }
+            PersonChangeKind::Deleted { id, .. } => {
+                searches.invalidate(&id);
+                cache.remove_at(&id, self.seq);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_people_are_not_validated_again() {
+        let long_nick = "a".repeat(64);
+        let json = format!(
+            r#"{{"seq": 1, "tipo": "created", "pessoa": {{"id": "018a5b7e-7f4c-7000-8000-000000000001", "nome": "", "apelido": "{long_nick}", "nascimento": "2000-01-01", "stack": ["Rust"]}}}}"#
+        );
+        let change = serde_json::from_str::<PersonChange>(&json).unwrap();
+        let PersonChangeKind::Created { person } = change.kind else {
+            panic!("not a creation");
+        };
+        assert_eq!(person.name.as_str(), "");
+        assert_eq!(person.nick.as_str(), long_nick);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt::Display, time::Duration};
+
+use serde::Serialize;
+
+/// Version of the latest migration in `db/`, which every migration records in `schema_version`.
+/// Instances are only ready once the database has it.
+pub const SCHEMA_VERSION: i32 = 7;
+
+/// Checks the latest version recorded in `schema_version`, if any, against [`SCHEMA_VERSION`].
+pub fn check_schema_version(applied: Option<i32>) -> Result<(), String> {
+    match applied {
+        Some(applied) if applied >= SCHEMA_VERSION => Ok(()),
+        Some(applied) => Err(format!(
+            "schema version {applied} is behind {SCHEMA_VERSION}"
+        )),
+        None => Err(String::from("no schema version recorded")),
+    }
+}
+
+/// How long a readiness check may wait on the database before it counts as failed.
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The outcome of one readiness check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthCheck {
+    pub ok: bool,
+    #[serde(rename = "mensagem", skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl HealthCheck {
+    pub fn passed() -> Self {
+        Self {
+            ok: true,
+            message: None,
+        }
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl<E: Display> From<Result<(), E>> for HealthCheck {
+    fn from(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Self::passed(),
+            Err(err) => Self::failed(err.to_string()),
+        }
+    }
+}
+
+/// Whether an instance can serve requests, check by check. Repositories without a database
+/// leave every check out, and so are always ready.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Readiness {
+    /// A round trip through a pooled connection.
+    #[serde(rename = "banco", skip_serializing_if = "Option::is_none")]
+    pub database: Option<HealthCheck>,
+    #[serde(rename = "migracoes", skip_serializing_if = "Option::is_none")]
+    pub migrations: Option<HealthCheck>,
+    /// The connection receiving person changes, without which cached people may be stale.
+    #[serde(rename = "eventos", skip_serializing_if = "Option::is_none")]
+    pub listener: Option<HealthCheck>,
+}
+
+#[derive(Serialize)]
+struct ReadinessBody<'a> {
+    #[serde(rename = "pronto")]
+    ready: bool,
+    #[serde(rename = "verificacoes")]
+    checks: &'a Readiness,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        [&self.database, &self.migrations, &self.listener]
+            .into_iter()
+            .flatten()
+            .all(|check| check.ok)
+    }
+
+    /// 503 while not ready, so load balancers stop sending requests.
+    pub fn status(&self) -> u16 {
+        if self.is_ready() {
+            200
+        } else {
+            503
+        }
+    }
+
+    /// The response body, with the overall answer next to each check.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(&ReadinessBody {
+            ready: self.is_ready(),
+            checks: self,
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_is_ready_from_the_latest_version_on() {
+        assert_eq!(check_schema_version(Some(SCHEMA_VERSION)), Ok(()));
+        assert_eq!(check_schema_version(Some(SCHEMA_VERSION + 1)), Ok(()));
+        assert!(check_schema_version(Some(SCHEMA_VERSION - 1)).is_err());
+        assert!(check_schema_version(None).is_err());
+    }
+}
@@ -1,7 +1,58 @@
-use serde::{Deserialize, Serialize};
-use time::Date;
+use std::io;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use time::{format_description::FormatItem, macros::format_description, Date};
 use uuid::Uuid;
 
+pub use api_error::{ApiError, ErrorCode};
+pub use birth_date::BirthDateRules;
+pub use cache::{CacheLimits, CacheStats, CacheWarmUp, PersonCache};
+pub use catch_up::{CatchUp, LAST_ID_QUERY, RESYNC_QUERY, WARM_UP_POLL_INTERVAL};
+pub use events::{PersonChange, PersonChangeKind, PERSON_CHANGES_CHANNEL};
+pub use health::{check_schema_version, HealthCheck, Readiness, READINESS_TIMEOUT, SCHEMA_VERSION};
+pub use listener::{Backoff, ListenerStats, ListenerStatus};
+pub use log_format::{LogFormat, DEFAULT_LOG_FILTER};
+pub use memory::InMemoryRepository;
+pub use metrics::{Metrics, PoolStats, RepositoryStats, METRICS_CONTENT_TYPE};
+pub use nick_filter::{NickFilter, NickFilterConfig, NickFilterStats};
+pub use options::{CountMode, RepositoryOptions};
+pub use persistence::{
+    AsyncPersonRepository, PersistenceError, PersistenceResult, PersonRepository,
+};
+pub use search::{SearchMode, SearchQuery, SearchRules, StackMatch, NEXT_CURSOR_HEADER};
+pub use search_cache::{SearchCache, SearchCacheConfig, SearchCacheStats};
+pub use search_term::{
+    contains_pattern, SearchTerm, MAX_SEARCH_TERM_LENGTH, MIN_SEARCH_TERM_LENGTH,
+};
+pub use stack::{normalize_stack, MAX_STACK_ENTRIES};
+pub use validation::{PayloadError, ValidationError, ValidationRule};
+pub use write_behind::{
+    BatchOutcome, BatchWrite, PendingPeople, WriteBehindConfig, WriteFailure, MAX_BATCH_ATTEMPTS,
+};
+
+mod api_error;
+mod birth_date;
+mod cache;
+mod catch_up;
+mod events;
+mod health;
+mod listener;
+mod log_format;
+mod memory;
+mod metrics;
+mod nick_filter;
+mod options;
+mod persistence;
+mod search;
+mod search_cache;
+mod search_term;
+mod stack;
+mod validation;
+mod write_behind;
+
+/// How many people a search returns at most.
+pub const MAX_SEARCH_RESULTS: usize = 50;
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
 pub struct Person {
@@ -15,19 +66,159 @@ pub struct Person {
     pub stack: Option<Vec<String>>,
 }
 
+impl Person {
+    /// Lowercase version of the `search` column searches run against.
+    pub(crate) fn search_text(&self) -> String {
+        let mut text = format!("{} {} ", self.name.as_str(), self.nick.as_str());
+        if let Some(stack) = &self.stack {
+            text.push_str(&stack.join(" "));
+        }
+        text.to_lowercase()
+    }
+}
+
 #[derive(Clone, Deserialize)]
+#[serde(try_from = "NewPersonPayload")]
 pub struct NewPerson {
-    #[serde(rename = "nome")]
     pub name: PersonName,
-    #[serde(rename = "apelido")]
     pub nick: Nick,
-    #[serde(rename = "nascimento", with = "date_format")]
     pub birth_date: Date,
     pub stack: Option<Vec<Tech>>,
 }
 
+impl NewPerson {
+    /// Parses and validates a person creation request body.
+    pub fn from_json(json: &[u8]) -> Result<Self, PayloadError> {
+        let payload = serde_json::from_slice::<NewPersonPayload>(json)?;
+        Ok(payload.try_into()?)
+    }
+
+    /// Same as [`NewPerson::from_json`], but reading the body from a stream.
+    pub fn from_reader(reader: impl io::Read) -> Result<Self, PayloadError> {
+        let payload = serde_json::from_reader::<_, NewPersonPayload>(reader)?;
+        Ok(payload.try_into()?)
+    }
+
+    /// Applies a JSON merge patch (RFC 7396) body over an existing person.
+    ///
+    /// Fields absent from the patch keep their current values and `null` removes them, which is
+    /// only allowed for `stack`. The result goes through the same validation as a new person.
+    pub fn from_patch(person: &Person, json: &[u8]) -> Result<Self, PayloadError> {
+        // Read as a map first, as a struct would also be taken from an array of its fields.
+        let patch = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(json)?;
+        let patch = PersonPatch::deserialize(serde_json::Value::Object(patch))?;
+        let payload = NewPersonPayload {
+            name: patch
+                .name
+                .unwrap_or_else(|| Some(person.name.as_str().to_owned())),
+            nick: patch
+                .nick
+                .unwrap_or_else(|| Some(person.nick.as_str().to_owned())),
+            birth_date: patch
+                .birth_date
+                .unwrap_or_else(|| person.birth_date.format(DATE_FORMAT).ok()),
+            stack: patch.stack.unwrap_or_else(|| person.stack.clone()),
+        };
+        Ok(payload.try_into()?)
+    }
+
+    pub fn into_person(self, id: Uuid) -> Person {
+        Person {
+            id,
+            name: self.name,
+            nick: self.nick,
+            birth_date: self.birth_date,
+            stack: self.stack.map(normalize_stack),
+        }
+    }
+}
+
+/// The shape of a person creation request, before any of its fields are validated.
+///
+/// Every field is optional here so that missing and `null` values are reported as validation
+/// errors, leaving deserialization failures only for malformed JSON and wrong types.
+#[derive(Clone, Deserialize)]
+pub struct NewPersonPayload {
+    #[serde(rename = "nome")]
+    pub name: Option<String>,
+    #[serde(rename = "apelido")]
+    pub nick: Option<String>,
+    #[serde(rename = "nascimento")]
+    pub birth_date: Option<String>,
+    pub stack: Option<Vec<String>>,
+}
+
+impl TryFrom<NewPersonPayload> for NewPerson {
+    type Error = ValidationError;
+
+    fn try_from(payload: NewPersonPayload) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: required("nome", payload.name)?.try_into()?,
+            nick: required("apelido", payload.nick)?.try_into()?,
+            birth_date: birth_date(payload.birth_date)?,
+            stack: payload
+                .stack
+                .map(|stack| {
+                    if stack.len() > MAX_STACK_ENTRIES {
+                        return Err(ValidationError::new(
+                            "stack",
+                            ValidationRule::MaxEntries {
+                                max: MAX_STACK_ENTRIES,
+                            },
+                        ));
+                    }
+
+                    stack
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, tech)| {
+                            Tech::try_from(tech).map_err(|err| err.at(format!("stack[{i}]")))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// A merge patch over a person, where the outer `Option` tells whether the field was present.
+#[derive(Deserialize)]
+struct PersonPatch {
+    #[serde(rename = "nome", default, deserialize_with = "present")]
+    name: Option<Option<String>>,
+    #[serde(rename = "apelido", default, deserialize_with = "present")]
+    nick: Option<Option<String>>,
+    #[serde(rename = "nascimento", default, deserialize_with = "present")]
+    birth_date: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    stack: Option<Option<Vec<String>>>,
+}
+
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn birth_date(value: Option<String>) -> Result<Date, ValidationError> {
+    let date = Date::parse(&required("nascimento", value)?, DATE_FORMAT)
+        .map_err(|_| ValidationError::new("nascimento", ValidationRule::InvalidFormat))?;
+    BirthDateRules::current().validate("nascimento", date)?;
+    Ok(date)
+}
+
+fn required<T>(field: &str, value: Option<T>) -> Result<T, ValidationError> {
+    value.ok_or_else(|| ValidationError::new(field, ValidationRule::Required))
+}
+
+/// Declares a validated string newtype.
+///
+/// Surrounding whitespace is removed first, and values made only of it are rejected as blank.
+/// Lengths are then counted in characters, like the `VARCHAR` columns backing them.
 macro_rules! new_string_type {
-    ($type:ident, max_length = $max_length:expr, error = $error_message:expr) => {
+    ($type:ident, field = $field:expr, length = $min:literal..=$max:literal) => {
         #[derive(Clone, Serialize, Deserialize)]
         #[serde(try_from = "String")]
         #[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
@@ -38,16 +229,37 @@ macro_rules! new_string_type {
             pub fn as_str(&self) -> &str {
                 &self.0
             }
+
+            /// Wraps a value read back from the database as is. It was checked when stored, maybe
+            /// against older rules, and refusing it now would only make its row unreadable.
+            pub fn from_stored(value: String) -> Self {
+                Self(value)
+            }
         }
 
         impl TryFrom<String> for $type {
-            type Error = &'static str;
+            type Error = ValidationError;
 
             fn try_from(value: String) -> Result<Self, Self::Error> {
-                if value.len() <= $max_length {
-                    Ok($type(value))
-                } else {
-                    Err($error_message)
+                let value = match value.trim() {
+                    trimmed if trimmed.len() != value.len() => trimmed.to_owned(),
+                    _ => value,
+                };
+
+                if value.is_empty() {
+                    return Err(ValidationError::new($field, ValidationRule::Blank));
+                }
+
+                match value.chars().count() {
+                    length if length < $min => Err(ValidationError::new(
+                        $field,
+                        ValidationRule::MinLength { min: $min },
+                    )),
+                    length if length > $max => Err(ValidationError::new(
+                        $field,
+                        ValidationRule::MaxLength { max: $max },
+                    )),
+                    _ => Ok($type(value)),
                 }
             }
         }
@@ -60,8 +272,222 @@ macro_rules! new_string_type {
     };
 }
 
-new_string_type!(PersonName, max_length = 100, error = "name is too big");
-new_string_type!(Nick, max_length = 32, error = "nick is too big");
-new_string_type!(Tech, max_length = 32, error = "tech is too big");
+new_string_type!(PersonName, field = "nome", length = 1..=100);
+new_string_type!(Nick, field = "apelido", length = 1..=32);
+new_string_type!(Tech, field = "stack", length = 1..=32);
+
+const DATE_FORMAT: &[FormatItem<'static>] = format_description!("[year]-[month]-[day]");
 
 time::serde::format_description!(date_format, Date, "[year]-[month]-[day]");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    new_string_type!(Code, field = "codigo", length = 3..=5);
+
+    /// Cycles through a name with multibyte characters, so it is longer in bytes than in chars.
+    fn multibyte(chars: usize) -> String {
+        "João Conceição".chars().cycle().take(chars).collect()
+    }
+
+    fn rule<T>(result: Result<T, ValidationError>) -> Option<ValidationRule> {
+        result.err().map(|err| err.rule)
+    }
+
+    #[test]
+    fn name_length_is_counted_in_chars() {
+        let name = multibyte(100);
+        assert!(name.len() > 100);
+        assert_eq!(
+            PersonName::try_from(name).unwrap().as_str().chars().count(),
+            100
+        );
+        assert_eq!(
+            rule(PersonName::try_from(multibyte(101))),
+            Some(ValidationRule::MaxLength { max: 100 })
+        );
+    }
+
+    #[test]
+    fn nick_length_is_counted_in_chars() {
+        let nick = multibyte(32);
+        assert!(nick.len() > 32);
+        assert!(Nick::try_from(nick.clone()).is_ok());
+        assert_eq!(
+            rule(Nick::try_from(format!("{nick}x"))),
+            Some(ValidationRule::MaxLength { max: 32 })
+        );
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let name = PersonName::try_from(String::from("  João Conceição \n")).unwrap();
+        assert_eq!(name.as_str(), "João Conceição");
+
+        let padded = format!(" {} ", multibyte(32));
+        assert!(Nick::try_from(padded).is_ok());
+    }
+
+    #[test]
+    fn blank_and_empty_values_are_rejected() {
+        for value in ["", " ", "\t\n"] {
+            assert_eq!(
+                rule(Nick::try_from(String::from(value))),
+                Some(ValidationRule::Blank)
+            );
+        }
+    }
+
+    #[test]
+    fn stored_values_are_not_checked_again() {
+        assert_eq!(PersonName::from_stored(String::new()).as_str(), "");
+        assert_eq!(Nick::from_stored(String::from(" joao ")).as_str(), " joao ");
+    }
+
+    #[test]
+    fn too_short_values_are_rejected() {
+        assert_eq!(
+            rule(Code::try_from(String::from(" çã "))),
+            Some(ValidationRule::MinLength { min: 3 })
+        );
+        assert!(Code::try_from(String::from("çãõ")).is_ok());
+        assert_eq!(
+            rule(Code::try_from(String::from("çãõçãõ"))),
+            Some(ValidationRule::MaxLength { max: 5 })
+        );
+    }
+
+    const VALID: &str = r#"{"nome": "João Conceição", "apelido": "joao", "nascimento": "2000-02-29", "stack": null}"#;
+
+    fn rejected(json: &str) -> PayloadError {
+        NewPerson::from_json(json.as_bytes())
+            .err()
+            .expect("the payload should be rejected")
+    }
+
+    fn with(field: &str, value: &str) -> String {
+        let mut payload = serde_json::from_str::<serde_json::Value>(VALID).unwrap();
+        payload[field] = serde_json::from_str(value).unwrap();
+        payload.to_string()
+    }
+
+    #[test]
+    fn valid_payloads_are_accepted() {
+        assert!(NewPerson::from_json(VALID.as_bytes()).is_ok());
+        assert!(NewPerson::from_reader(VALID.as_bytes()).is_ok());
+        assert!(NewPerson::from_json(with("stack", r#"["Rust", "Node"]"#).as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn wrong_types_and_malformed_bodies_are_syntax_errors() {
+        let payloads = [
+            with("nome", "1"),
+            with("stack", "[1]"),
+            with("stack", r#""x""#),
+            String::from("not json"),
+            String::from(""),
+            String::from(r#"{"nome": "João""#),
+        ];
+        for payload in payloads {
+            assert!(
+                matches!(rejected(&payload), PayloadError::Syntax(_)),
+                "{payload} should be a syntax error"
+            );
+        }
+    }
+
+    #[test]
+    fn rule_violations_are_invalid_payloads() {
+        let cases = [
+            (with("nome", "null"), "nome", ValidationRule::Required),
+            (
+                VALID.replace(r#""apelido": "joao", "#, ""),
+                "apelido",
+                ValidationRule::Required,
+            ),
+            (
+                with("nascimento", r#""abc""#),
+                "nascimento",
+                ValidationRule::InvalidFormat,
+            ),
+            (with("stack", r#"[""]"#), "stack[0]", ValidationRule::Blank),
+        ];
+        for (payload, field, rule) in cases {
+            match rejected(&payload) {
+                PayloadError::Invalid(err) => assert_eq!(err, ValidationError::new(field, rule)),
+                PayloadError::Syntax(err) => panic!("{payload} should be invalid, not {err}"),
+            }
+        }
+    }
+
+    fn current() -> Person {
+        Person {
+            id: Uuid::nil(),
+            name: PersonName::from_stored(String::from("João Conceição")),
+            nick: Nick::from_stored(String::from("joao")),
+            birth_date: time::macros::date!(2000 - 02 - 29),
+            stack: Some(vec![String::from("Rust")]),
+        }
+    }
+
+    fn patched(json: &str) -> Result<Person, PayloadError> {
+        NewPerson::from_patch(&current(), json.as_bytes())
+            .map(|person| person.into_person(Uuid::nil()))
+    }
+
+    #[test]
+    fn absent_fields_keep_their_values() {
+        let person = patched(r#"{"apelido": "jj"}"#).ok().unwrap();
+        assert_eq!(person.name.as_str(), "João Conceição");
+        assert_eq!(person.nick.as_str(), "jj");
+        assert_eq!(person.birth_date, time::macros::date!(2000 - 02 - 29));
+        assert_eq!(person.stack, Some(vec![String::from("Rust")]));
+
+        let person = patched("{}").ok().unwrap();
+        assert_eq!(person.nick.as_str(), "joao");
+    }
+
+    #[test]
+    fn null_clears_the_stack() {
+        let person = patched(r#"{"stack": null}"#).ok().unwrap();
+        assert_eq!(person.stack, None);
+
+        let person = patched(r#"{"stack": ["Go"]}"#).ok().unwrap();
+        assert_eq!(person.stack, Some(vec![String::from("Go")]));
+    }
+
+    #[test]
+    fn null_required_fields_are_rejected() {
+        for field in ["nome", "apelido", "nascimento"] {
+            match patched(&format!(r#"{{"{field}": null}}"#)) {
+                Err(PayloadError::Invalid(err)) => {
+                    assert_eq!(err, ValidationError::new(field, ValidationRule::Required))
+                }
+                _ => panic!("a null {field} should be invalid"),
+            }
+        }
+    }
+
+    #[test]
+    fn patched_values_are_validated() {
+        assert!(matches!(
+            patched(r#"{"nascimento": "abc"}"#),
+            Err(PayloadError::Invalid(err)) if err.rule == ValidationRule::InvalidFormat
+        ));
+        assert!(matches!(
+            patched(r#"{"nome": 1}"#),
+            Err(PayloadError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn bodies_other_than_objects_are_rejected() {
+        for body in ["null", "[]", r#""joao""#, "1", "not json"] {
+            assert!(
+                matches!(patched(body), Err(PayloadError::Syntax(_))),
+                "{body} should be a syntax error"
+            );
+        }
+    }
+}
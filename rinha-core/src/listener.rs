@@ -0,0 +1,123 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+use crate::{PersonChange, PersonChangeKind};
+
+/// What an instance knows about its connection to the person change events.
+#[derive(Default)]
+pub struct ListenerStatus {
+    connected: AtomicBool,
+    last_seq: AtomicU64,
+    last_id: Mutex<Option<Uuid>>,
+    lag_micros: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListenerStats {
+    pub connected: bool,
+    pub last_seq: u64,
+    /// How long the last created person took to get here, from when its id was generated.
+    pub lag: Duration,
+}
+
+impl ListenerStatus {
+    /// Whether changes are being received right now. While they are not, anything cached from
+    /// previous changes may be stale.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Release);
+    }
+
+    /// Records a received change, stale or not.
+    pub fn observe(&self, change: &PersonChange) {
+        self.last_seq.fetch_max(change.seq, Ordering::Relaxed);
+
+        if let PersonChangeKind::Created { person } = &change.kind {
+            self.see_id(person.id);
+            if let Some(lag) = age(person.id) {
+                self.lag_micros
+                    .store(lag.as_micros() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records that a person is known to exist. Ids are UUIDv7, so the highest one seen tells
+    /// from where to resync after a reconnection.
+    pub fn see_id(&self, id: Uuid) {
+        let mut last_id = self.last_id.lock().unwrap();
+        if last_id.is_none_or(|last_id| id > last_id) {
+            *last_id = Some(id);
+        }
+    }
+
+    pub fn last_id(&self) -> Option<Uuid> {
+        *self.last_id.lock().unwrap()
+    }
+
+    /// The highest change sequence received so far, zero when none was.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> ListenerStats {
+        ListenerStats {
+            connected: self.is_connected(),
+            last_seq: self.last_seq(),
+            lag: Duration::from_micros(self.lag_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// How long ago a UUIDv7 was generated, going by its embedded timestamp.
+fn age(id: Uuid) -> Option<Duration> {
+    let (secs, nanos) = id.get_timestamp()?.to_unix();
+    let generated_at = SystemTime::UNIX_EPOCH + Duration::new(secs, nanos);
+    Some(
+        SystemTime::now()
+            .duration_since(generated_at)
+            .unwrap_or_default(),
+    )
+}
+
+/// Exponential backoff between reconnection attempts.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), Duration::from_secs(10))
+    }
+}
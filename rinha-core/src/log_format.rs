@@ -0,0 +1,30 @@
+use std::str::FromStr;
+
+/// How both servers write their logs to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, colored lines.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "" | "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format: {other}")),
+        }
+    }
+}
+
+/// The log filter used when `RUST_LOG` is not set. The servers log startup, like cache warm-up
+/// progress, at `info`. Every request is logged at `info` too, under `tower_http` in axum and
+/// `rinha_touche::requests` in touche, which is too much under load, so only failed requests are
+/// logged unless asked for.
+pub const DEFAULT_LOG_FILTER: &str =
+    "warn,rinha_axum=info,rinha_touche=info,rinha_touche::requests=warn";
@@ -0,0 +1,426 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+use dashmap::{mapref::entry::Entry, DashMap};
+use uuid::Uuid;
+
+use crate::{
+    search::LowercasePerson, AsyncPersonRepository, NewPerson, PersistenceError, PersistenceResult,
+    Person, PersonRepository, SearchMode, SearchQuery,
+};
+
+/// Same as the default `pg_trgm.similarity_threshold`.
+const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+/// Keeps everybody in process memory, so the servers can run without a database.
+///
+/// Substring search mimics the trigram index used on Postgres: every person is indexed by the
+/// trigrams of its search text, candidates are the intersection of the query trigrams and are then
+/// confirmed with a plain substring match.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    people: DashMap<Uuid, Person>,
+    nicks: DashMap<String, Uuid>,
+    trigrams: DashMap<String, HashSet<Uuid>>,
+    count: AtomicU64,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn reserve_nick(&self, nick: &str, id: Uuid) -> PersistenceResult<()> {
+        match self.nicks.entry(nick.to_owned()) {
+            Entry::Occupied(_) => Err(PersistenceError::UniqueViolation),
+            Entry::Vacant(entry) => {
+                entry.insert(id);
+                Ok(())
+            }
+        }
+    }
+
+    fn index(&self, person: &Person) {
+        for trigram in trigrams(&person.search_text()) {
+            self.trigrams.entry(trigram).or_default().insert(person.id);
+        }
+    }
+
+    fn unindex(&self, person: &Person) {
+        for trigram in trigrams(&person.search_text()) {
+            if let Some(mut ids) = self.trigrams.get_mut(&trigram) {
+                ids.remove(&person.id);
+            }
+        }
+    }
+
+    fn search_substring(&self, term: &str, query: &SearchQuery) -> Vec<Person> {
+        let query_trigrams = trigrams(term);
+
+        let matches = |person: &Person| query.matches(&LowercasePerson::new(person));
+
+        if query_trigrams.is_empty() {
+            return page(
+                self.people
+                    .iter()
+                    .filter(|entry| matches(entry.value()))
+                    .map(|entry| entry.value().clone()),
+                query,
+            );
+        }
+
+        let mut candidates: Option<HashSet<Uuid>> = None;
+        for trigram in &query_trigrams {
+            let ids = match self.trigrams.get(trigram) {
+                Some(ids) => ids,
+                None => return Vec::new(),
+            };
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&ids).copied().collect(),
+                None => ids.clone(),
+            });
+        }
+
+        page(
+            candidates
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.people.get(&id).map(|entry| entry.value().clone()))
+                .filter(matches),
+            query,
+        )
+    }
+
+    /// Ranks people by the share of trigrams their search text has in common with the query,
+    /// like `similarity()` does in Postgres.
+    fn search_similar(&self, term: &str, query: &SearchQuery) -> Vec<Person> {
+        let query_trigrams = trigrams(term);
+
+        let candidates = query_trigrams
+            .iter()
+            .filter_map(|trigram| self.trigrams.get(trigram))
+            .flat_map(|ids| ids.iter().copied().collect::<Vec<_>>())
+            .collect::<HashSet<_>>();
+
+        let mut ranked = candidates
+            .into_iter()
+            .filter_map(|id| self.people.get(&id).map(|entry| entry.value().clone()))
+            .filter(|person| query.matches_filters(&LowercasePerson::new(person)))
+            .map(|person| {
+                let person_trigrams = trigrams(&person.search_text());
+                let common = person_trigrams.intersection(&query_trigrams).count();
+                let all = person_trigrams.len() + query_trigrams.len() - common;
+                (common as f32 / all as f32, person)
+            })
+            .filter(|(similarity, _)| *similarity >= SIMILARITY_THRESHOLD)
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        ranked
+            .into_iter()
+            .take(query.limit())
+            .map(|(_, person)| person)
+            .collect()
+    }
+
+    /// Matches people whose search text has every word of the query. Unlike Postgres, words are
+    /// not stemmed and results are not ranked.
+    fn search_words(&self, term: &str, query: &SearchQuery) -> Vec<Person> {
+        let words = term.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        self.people
+            .iter()
+            .filter(|entry| {
+                let person = LowercasePerson::new(entry.value());
+                let person_words = person
+                    .search_text
+                    .split_whitespace()
+                    .collect::<HashSet<_>>();
+                words.iter().all(|word| person_words.contains(word))
+                    && query.matches_filters(&person)
+            })
+            .take(query.limit())
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+}
+
+/// Sorts unranked results by id and keeps the page after the cursor, like the keyset pagination
+/// done on Postgres.
+fn page(people: impl Iterator<Item = Person>, query: &SearchQuery) -> Vec<Person> {
+    let mut people = people
+        .filter(|person| query.cursor.is_none_or(|cursor| person.id > cursor))
+        .collect::<Vec<_>>();
+    people.sort_unstable_by_key(|person| person.id);
+    people.truncate(query.limit());
+    people
+}
+
+fn trigrams(text: &str) -> HashSet<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .windows(3)
+        .map(|window| window.iter().collect::<String>())
+        .collect()
+}
+
+impl PersonRepository for InMemoryRepository {
+    fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid> {
+        let id = Uuid::now_v7();
+        let person = new_person.into_person(id);
+
+        self.reserve_nick(person.nick.as_str(), id)?;
+        self.index(&person);
+        self.people.insert(id, person);
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        Ok(id)
+    }
+
+    fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
+        Ok(self.people.get(&id).map(|entry| entry.value().clone()))
+    }
+
+    fn update_person(&self, id: Uuid, person: NewPerson) -> PersistenceResult<Option<Person>> {
+        // The entry stays locked until the person is replaced, so concurrent updates and deletes
+        // of the same id see each other's nick and index changes whole.
+        let Some(mut previous) = self.people.get_mut(&id) else {
+            return Ok(None);
+        };
+        let person = person.into_person(id);
+
+        if person.nick.as_str() != previous.nick.as_str() {
+            self.reserve_nick(person.nick.as_str(), id)?;
+            self.nicks
+                .remove_if(previous.nick.as_str(), |_, owner| *owner == id);
+        }
+
+        self.unindex(&previous);
+        self.index(&person);
+        *previous = person.clone();
+
+        Ok(Some(person))
+    }
+
+    fn delete_person(&self, id: Uuid) -> PersistenceResult<bool> {
+        match self.people.entry(id) {
+            Entry::Occupied(entry) => {
+                let person = entry.get();
+                self.nicks
+                    .remove_if(person.nick.as_str(), |_, owner| *owner == id);
+                self.unindex(person);
+                entry.remove();
+                self.count.fetch_sub(1, Ordering::Relaxed);
+                Ok(true)
+            }
+            Entry::Vacant(_) => Ok(false),
+        }
+    }
+
+    fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>> {
+        let query = &query.to_lowercase();
+        let Some(term) = &query.term else {
+            return Ok(page(
+                self.people
+                    .iter()
+                    .filter(|entry| query.matches_filters(&LowercasePerson::new(entry.value())))
+                    .map(|entry| entry.value().clone()),
+                query,
+            ));
+        };
+
+        let term = term.as_str();
+        Ok(match query.mode {
+            SearchMode::Substring => self.search_substring(term, query),
+            SearchMode::Trigram => self.search_similar(term, query),
+            SearchMode::FullText => self.search_words(term, query),
+        })
+    }
+
+    fn count_people(&self) -> PersistenceResult<u64> {
+        Ok(self.count.load(Ordering::Relaxed))
+    }
+}
+
+#[async_trait]
+impl AsyncPersonRepository for InMemoryRepository {
+    async fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid> {
+        PersonRepository::create_person(self, new_person)
+    }
+
+    async fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
+        PersonRepository::find_person(self, id)
+    }
+
+    async fn update_person(
+        &self,
+        id: Uuid,
+        person: NewPerson,
+    ) -> PersistenceResult<Option<Person>> {
+        PersonRepository::update_person(self, id, person)
+    }
+
+    async fn delete_person(&self, id: Uuid) -> PersistenceResult<bool> {
+        PersonRepository::delete_person(self, id)
+    }
+
+    async fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>> {
+        PersonRepository::search_people(self, query)
+    }
+
+    async fn count_people(&self) -> PersistenceResult<u64> {
+        PersonRepository::count_people(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchTerm;
+
+    fn new_person(name: &str, nick: &str, stack: &[&str]) -> NewPerson {
+        let json = serde_json::json!({
+            "nome": name,
+            "apelido": nick,
+            "nascimento": "2000-01-01",
+            "stack": stack,
+        });
+        NewPerson::from_json(json.to_string().as_bytes())
+            .ok()
+            .unwrap()
+    }
+
+    fn search(repo: &InMemoryRepository, term: &str) -> Vec<String> {
+        let query = SearchQuery {
+            term: Some(SearchTerm::try_from(term.to_owned()).ok().unwrap()),
+            ..SearchQuery::default()
+        };
+        PersonRepository::search_people(repo, &query)
+            .unwrap()
+            .into_iter()
+            .map(|person| person.nick.as_str().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn searches_see_updates() {
+        let repo = InMemoryRepository::new();
+        let id =
+            PersonRepository::create_person(&repo, new_person("João", "joao", &["Rust"])).unwrap();
+        assert_eq!(search(&repo, "rust"), ["joao"]);
+
+        PersonRepository::update_person(&repo, id, new_person("João", "joao", &["Elixir"]))
+            .unwrap();
+        assert!(search(&repo, "rust").is_empty());
+        assert_eq!(search(&repo, "ELIXIR"), ["joao"]);
+    }
+
+    #[test]
+    fn searches_forget_deleted_people() {
+        let repo = InMemoryRepository::new();
+        let id =
+            PersonRepository::create_person(&repo, new_person("João", "joao", &["Rust"])).unwrap();
+        PersonRepository::create_person(&repo, new_person("José", "jose", &["Rust"])).unwrap();
+
+        assert!(PersonRepository::delete_person(&repo, id).unwrap());
+        assert_eq!(search(&repo, "rust"), ["jose"]);
+        assert_eq!(PersonRepository::count_people(&repo).unwrap(), 1);
+    }
+
+    #[test]
+    fn nicks_stay_unique_across_updates() {
+        let repo = InMemoryRepository::new();
+        let joao = PersonRepository::create_person(&repo, new_person("João", "joao", &[])).unwrap();
+        let jose = PersonRepository::create_person(&repo, new_person("José", "jose", &[])).unwrap();
+
+        assert!(matches!(
+            PersonRepository::update_person(&repo, jose, new_person("José", "joao", &[])),
+            Err(PersistenceError::UniqueViolation)
+        ));
+
+        // The nick given up by an update can be taken again.
+        PersonRepository::update_person(&repo, joao, new_person("João", "jj", &[])).unwrap();
+        PersonRepository::update_person(&repo, jose, new_person("José", "joao", &[])).unwrap();
+        assert!(matches!(
+            PersonRepository::create_person(&repo, new_person("Jota", "jj", &[])),
+            Err(PersistenceError::UniqueViolation)
+        ));
+        assert!(PersonRepository::create_person(&repo, new_person("Jota", "jose", &[])).is_ok());
+    }
+
+    #[test]
+    fn terms_without_trigrams_are_matched_as_substrings() {
+        let repo = InMemoryRepository::new();
+        PersonRepository::create_person(&repo, new_person("João", "joao", &["Go"])).unwrap();
+        PersonRepository::create_person(&repo, new_person("Maria", "maria", &["C#"])).unwrap();
+
+        assert_eq!(search(&repo, "go"), ["joao"]);
+        assert_eq!(search(&repo, "C#"), ["maria"]);
+        assert!(search(&repo, "zz").is_empty());
+    }
+
+    #[test]
+    fn concurrent_updates_leave_one_nick_reserved() {
+        let repo = InMemoryRepository::new();
+        let id = PersonRepository::create_person(&repo, new_person("João", "joao", &[])).unwrap();
+
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let repo = &repo;
+                scope.spawn(move || {
+                    for round in 0..50 {
+                        let nick = format!("joao{n}_{round}");
+                        PersonRepository::update_person(repo, id, new_person("João", &nick, &[]))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let person = PersonRepository::find_person(&repo, id).unwrap().unwrap();
+        assert_eq!(repo.nicks.len(), 1);
+        assert_eq!(
+            repo.nicks.get(person.nick.as_str()).map(|owner| *owner),
+            Some(id)
+        );
+    }
+
+    #[test]
+    fn deleted_people_stay_deleted_when_updated_at_the_same_time() {
+        let repo = InMemoryRepository::new();
+        let ids = (0..50)
+            .map(|n| {
+                let nick = format!("joao{n}");
+                PersonRepository::create_person(&repo, new_person("João", &nick, &["Rust"]))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for (n, id) in ids.iter().enumerate() {
+                    let nick = format!("jose{n}");
+                    PersonRepository::update_person(&repo, *id, new_person("José", &nick, &["Go"]))
+                        .unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for id in &ids {
+                    assert!(PersonRepository::delete_person(&repo, *id).unwrap());
+                }
+            });
+        });
+
+        assert!(repo.people.is_empty());
+        assert!(repo.nicks.is_empty());
+        assert_eq!(PersonRepository::count_people(&repo).unwrap(), 0);
+        assert!(search(&repo, "rust").is_empty());
+        assert!(search(&repo, "jos").is_empty());
+    }
+}
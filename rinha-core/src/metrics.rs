@@ -0,0 +1,442 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+
+use crate::{CacheStats, ListenerStats, NickFilterStats, SearchCacheStats};
+
+/// Content type of [`Metrics::render`], the Prometheus text exposition format.
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds of the request latency buckets, in microseconds.
+const LATENCY_BUCKETS: [u64; 13] = [
+    500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000,
+];
+
+/// Connections of a database pool.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoolStats {
+    /// Open connections, idle or not.
+    pub size: u32,
+    pub idle: u32,
+    pub max_size: u32,
+}
+
+/// What a repository can tell about itself, each part only when it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepositoryStats {
+    pub pool: Option<PoolStats>,
+    pub cache: Option<CacheStats>,
+    pub searches: Option<SearchCacheStats>,
+    pub nicks: Option<NickFilterStats>,
+    pub listener: Option<ListenerStats>,
+}
+
+/// Label of requests no route handled.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Request counts and latencies of a server, by route, method and status.
+///
+/// Requests are labelled with the route the server dispatched them to, in the syntax of axum,
+/// never with their path, so ids don't end up in labels.
+#[derive(Default)]
+pub struct Metrics {
+    requests: DashMap<String, DashMap<RequestKey, Histogram>>,
+}
+
+type RequestKey = (&'static str, u16);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a request handled by `route`, `None` when no route was found for it.
+    pub fn record(&self, route: Option<&str>, method: &str, status: u16, latency: Duration) {
+        let route = route.unwrap_or(UNMATCHED_ROUTE);
+        let key = (method_label(method), status);
+        let latency = latency.as_micros() as u64;
+
+        let observe = |histogram: &Histogram| {
+            if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| latency <= le) {
+                histogram.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            }
+            histogram.count.fetch_add(1, Ordering::Relaxed);
+            histogram.sum_micros.fetch_add(latency, Ordering::Relaxed);
+        };
+        let requests = match self.requests.get(route) {
+            Some(requests) => requests,
+            None => self
+                .requests
+                .entry(route.to_owned())
+                .or_default()
+                .downgrade(),
+        };
+        match requests.get(&key) {
+            Some(histogram) => observe(&histogram),
+            None => observe(&requests.entry(key).or_default()),
+        };
+    }
+
+    /// Writes every metric, along with the stats of the repository, in the Prometheus text
+    /// format.
+    pub fn render(&self, repository: &RepositoryStats) -> String {
+        let mut out = String::new();
+
+        let mut requests = self
+            .requests
+            .iter()
+            .flat_map(|route| {
+                route
+                    .value()
+                    .iter()
+                    .map(|entry| {
+                        let histogram = entry.value();
+                        let buckets = histogram
+                            .buckets
+                            .iter()
+                            .map(|bucket| bucket.load(Ordering::Relaxed))
+                            .collect::<Vec<_>>();
+                        let count = histogram.count.load(Ordering::Relaxed);
+                        let sum = histogram.sum_micros.load(Ordering::Relaxed);
+                        let (method, status) = *entry.key();
+                        ((route.key().clone(), method, status), buckets, count, sum)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        requests.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        header(
+            &mut out,
+            "rinha_http_requests_total",
+            "counter",
+            "Requests handled.",
+        );
+        for ((route, method, status), _, count, _) in &requests {
+            let labels = format!(r#"route="{route}",method="{method}",status="{status}""#);
+            writeln!(out, "rinha_http_requests_total{{{labels}}} {count}").unwrap();
+        }
+
+        header(
+            &mut out,
+            "rinha_http_request_duration_seconds",
+            "histogram",
+            "Time taken to handle requests.",
+        );
+        for ((route, method, status), buckets, count, sum) in &requests {
+            let labels = format!(r#"route="{route}",method="{method}",status="{status}""#);
+            let mut cumulative = 0;
+            for (le, bucket) in LATENCY_BUCKETS.iter().zip(buckets) {
+                cumulative += bucket;
+                let le = *le as f64 / 1e6;
+                writeln!(
+                    out,
+                    "rinha_http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "rinha_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "rinha_http_request_duration_seconds_sum{{{labels}}} {}",
+                *sum as f64 / 1e6
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "rinha_http_request_duration_seconds_count{{{labels}}} {count}"
+            )
+            .unwrap();
+        }
+
+        if let Some(pool) = &repository.pool {
+            gauge(
+                &mut out,
+                "rinha_db_pool_connections",
+                "Open database connections.",
+                pool.size,
+            );
+            gauge(
+                &mut out,
+                "rinha_db_pool_idle_connections",
+                "Open database connections not in use.",
+                pool.idle,
+            );
+            gauge(
+                &mut out,
+                "rinha_db_pool_max_connections",
+                "Database connections the pool may open.",
+                pool.max_size,
+            );
+        }
+
+        if let Some(cache) = &repository.cache {
+            gauge(
+                &mut out,
+                "rinha_cache_entries",
+                "People in the cache.",
+                cache.entries,
+            );
+            gauge(
+                &mut out,
+                "rinha_cache_bytes",
+                "Estimated memory taken by the cache.",
+                cache.bytes,
+            );
+            counter(
+                &mut out,
+                "rinha_cache_hits_total",
+                "Lookups found in the cache.",
+                cache.hits,
+            );
+            counter(
+                &mut out,
+                "rinha_cache_misses_total",
+                "Lookups not found in the cache.",
+                cache.misses,
+            );
+            counter(
+                &mut out,
+                "rinha_cache_evictions_total",
+                "People evicted from the cache to stay within its limits.",
+                cache.evictions,
+            );
+        }
+
+        if let Some(searches) = &repository.searches {
+            gauge(
+                &mut out,
+                "rinha_search_cache_entries",
+                "Search results in the cache.",
+                searches.entries,
+            );
+            counter(
+                &mut out,
+                "rinha_search_cache_hits_total",
+                "Searches answered from the cache.",
+                searches.hits,
+            );
+            counter(
+                &mut out,
+                "rinha_search_cache_misses_total",
+                "Searches that had to query the database.",
+                searches.misses,
+            );
+            counter(
+                &mut out,
+                "rinha_search_cache_invalidations_total",
+                "Search results dropped because of changed people.",
+                searches.invalidations,
+            );
+        }
+
+        if let Some(nicks) = &repository.nicks {
+            gauge(
+                &mut out,
+                "rinha_nick_filter_bytes",
+                "Size of the nick filter.",
+                nicks.bytes,
+            );
+            header(
+                &mut out,
+                "rinha_nick_filter_checks_total",
+                "counter",
+                "Nicks checked against the filter, by answer.",
+            );
+            writeln!(
+                out,
+                "rinha_nick_filter_checks_total{{result=\"maybe\"}} {}",
+                nicks.maybe
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "rinha_nick_filter_checks_total{{result=\"absent\"}} {}",
+                nicks.absent
+            )
+            .unwrap();
+        }
+
+        if let Some(listener) = &repository.listener {
+            gauge(
+                &mut out,
+                "rinha_listener_connected",
+                "Whether person changes are being received.",
+                u8::from(listener.connected),
+            );
+            gauge(
+                &mut out,
+                "rinha_listener_last_seq",
+                "Sequence of the last person change received.",
+                listener.last_seq,
+            );
+            gauge(
+                &mut out,
+                "rinha_listener_lag_seconds",
+                "Time from creating the last created person to receiving its change.",
+                listener.lag.as_secs_f64(),
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        _ => "OTHER",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(out: &str, prefix: &str) -> Vec<String> {
+        out.lines()
+            .filter(|line| line.starts_with(prefix))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn requests_are_counted_by_route_method_and_status() {
+        let metrics = Metrics::new();
+        let latency = Duration::from_millis(1);
+        metrics.record(Some("/pessoas/:id"), "GET", 200, latency);
+        metrics.record(Some("/pessoas/:id"), "GET", 200, latency);
+        metrics.record(Some("/pessoas/:id"), "GET", 404, latency);
+        metrics.record(Some("/pessoas"), "BREW", 405, latency);
+        metrics.record(None, "GET", 404, latency);
+
+        let out = metrics.render(&RepositoryStats::default());
+        assert_eq!(
+            lines(&out, "rinha_http_requests_total{"),
+            [
+                r#"rinha_http_requests_total{route="/pessoas",method="OTHER",status="405"} 1"#,
+                r#"rinha_http_requests_total{route="/pessoas/:id",method="GET",status="200"} 2"#,
+                r#"rinha_http_requests_total{route="/pessoas/:id",method="GET",status="404"} 1"#,
+                r#"rinha_http_requests_total{route="unmatched",method="GET",status="404"} 1"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn latencies_fall_in_cumulative_buckets() {
+        let metrics = Metrics::new();
+        for micros in [400, 500, 501, 7_000, 10_000_000] {
+            metrics.record(Some("/pessoas"), "GET", 200, Duration::from_micros(micros));
+        }
+
+        let out = metrics.render(&RepositoryStats::default());
+        let labels = r#"route="/pessoas",method="GET",status="200""#;
+        let bucket = |le: &str| {
+            let prefix =
+                format!("rinha_http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} ");
+            lines(&out, &prefix)[0][prefix.len()..].to_owned()
+        };
+        assert_eq!(bucket("0.0005"), "2");
+        assert_eq!(bucket("0.001"), "3");
+        assert_eq!(bucket("0.005"), "3");
+        assert_eq!(bucket("0.01"), "4");
+        assert_eq!(bucket("5"), "4");
+        assert_eq!(bucket("+Inf"), "5");
+        assert_eq!(
+            lines(&out, "rinha_http_request_duration_seconds_count{"),
+            [format!(
+                "rinha_http_request_duration_seconds_count{{{labels}}} 5"
+            )]
+        );
+        assert_eq!(
+            lines(&out, "rinha_http_request_duration_seconds_sum{"),
+            [format!(
+                "rinha_http_request_duration_seconds_sum{{{labels}}} 10.008401"
+            )]
+        );
+    }
+
+    #[test]
+    fn every_metric_has_help_and_type() {
+        let metrics = Metrics::new();
+        metrics.record(Some("/pessoas"), "POST", 201, Duration::from_millis(1));
+        let out = metrics.render(&RepositoryStats {
+            pool: Some(PoolStats {
+                size: 3,
+                idle: 1,
+                max_size: 10,
+            }),
+            cache: Some(CacheStats::default()),
+            searches: Some(SearchCacheStats::default()),
+            nicks: Some(NickFilterStats::default()),
+            listener: Some(ListenerStats::default()),
+        });
+
+        let mut described = vec![];
+        for line in out.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                described.push(help.split(' ').next().unwrap().to_owned());
+            } else if let Some(kind) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = kind.split_once(' ').unwrap();
+                assert_eq!(described.last().map(String::as_str), Some(name));
+                assert!(["counter", "gauge", "histogram"].contains(&kind));
+            } else {
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert!(
+                    described
+                        .iter()
+                        .any(|described| name.starts_with(described.as_str())),
+                    "{line} has no HELP"
+                );
+                let value = line.rsplit(' ').next().unwrap();
+                assert!(value.parse::<f64>().is_ok(), "{line} has no value");
+            }
+        }
+        assert!(out.contains("\nrinha_db_pool_connections 3\n"));
+        assert!(out.contains("\nrinha_db_pool_max_connections 10\n"));
+        assert!(out.contains("\nrinha_listener_connected 0\n"));
+    }
+
+    #[test]
+    fn only_stats_the_repository_has_are_rendered() {
+        let out = Metrics::new().render(&RepositoryStats::default());
+        assert!(!out.contains("rinha_db_pool"));
+        assert!(!out.contains("rinha_cache"));
+        assert!(out.contains("# TYPE rinha_http_requests_total counter"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    f64::consts::LN_2,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct NickFilterConfig {
+    /// How many nicks the filter is sized for. Past that the false positive rate degrades.
+    pub capacity: usize,
+    pub false_positive_rate: f64,
+}
+
+impl Default for NickFilterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1_000_000,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// Bloom filter of the nicks already taken.
+///
+/// Its memory is fixed when created, no matter how many nicks are inserted. It can tell for sure
+/// that a nick was never inserted, but a positive answer only means the nick may have been, so
+/// those must still be confirmed by the database, which servers leave to the unique constraint so
+/// a taken nick raises no error. Nicks can't be removed, which is fine, as a stale nick only turns
+/// into another "maybe".
+pub struct NickFilter {
+    bits: Vec<AtomicU64>,
+    len: u64,
+    hashes: u32,
+    maybe: AtomicU64,
+    absent: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NickFilterStats {
+    /// Size of the bit array, in bytes.
+    pub bytes: usize,
+    /// Checks that had to be confirmed by the database.
+    pub maybe: u64,
+    /// Checks that spared a trip to the database.
+    pub absent: u64,
+}
+
+impl NickFilter {
+    pub fn new(config: NickFilterConfig) -> Self {
+        let capacity = config.capacity.max(1) as f64;
+        let false_positive_rate = config.false_positive_rate.clamp(f64::EPSILON, 0.5);
+
+        let len = (-capacity * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let len = len.max(64);
+        let hashes = ((len as f64 / capacity) * LN_2).round().clamp(1.0, 32.0) as u32;
+
+        Self {
+            bits: (0..len.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            len,
+            hashes,
+            maybe: AtomicU64::new(0),
+            absent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn insert(&self, nick: &str) {
+        for bit in self.bits_of(nick) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// `false` when the nick was surely never inserted.
+    pub fn may_contain(&self, nick: &str) -> bool {
+        let maybe = self.bits_of(nick).all(|bit| {
+            self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        });
+        let counter = if maybe { &self.maybe } else { &self.absent };
+        counter.fetch_add(1, Ordering::Relaxed);
+        maybe
+    }
+
+    /// Size of the bit array, in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    pub fn stats(&self) -> NickFilterStats {
+        NickFilterStats {
+            bytes: self.size(),
+            maybe: self.maybe.load(Ordering::Relaxed),
+            absent: self.absent.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Positions of a nick using double hashing, as in Kirsch and Mitzenmacher.
+    fn bits_of(&self, nick: &str) -> impl Iterator<Item = u64> {
+        let first = hash(0, nick);
+        let second = hash(1, nick) | 1;
+        let len = self.len;
+        (0..u64::from(self.hashes)).map(move |i| first.wrapping_add(i.wrapping_mul(second)) % len)
+    }
+}
+
+fn hash(seed: u64, nick: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    nick.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(capacity: usize, false_positive_rate: f64) -> NickFilter {
+        NickFilter::new(NickFilterConfig {
+            capacity,
+            false_positive_rate,
+        })
+    }
+
+    #[test]
+    fn inserted_nicks_are_never_reported_absent() {
+        let filter = filter(1_000, 0.01);
+        let nicks = (0..5_000).map(|i| format!("nick{i}")).collect::<Vec<_>>();
+        for nick in &nicks {
+            filter.insert(nick);
+        }
+        assert!(nicks.iter().all(|nick| filter.may_contain(nick)));
+    }
+
+    #[test]
+    fn is_sized_for_its_capacity_and_false_positive_rate() {
+        let sized = filter(1_000, 0.01);
+        assert_eq!(sized.len, 9_586);
+        assert_eq!(sized.hashes, 7);
+        assert_eq!(sized.size(), 9_586_usize.div_ceil(64) * 8);
+
+        let tiny = filter(1, 0.5);
+        assert_eq!(tiny.len, 64);
+        assert_eq!(tiny.hashes, 32);
+    }
+
+    #[test]
+    fn false_positives_stay_around_the_configured_rate() {
+        let filter = filter(1_000, 0.01);
+        for i in 0..1_000 {
+            filter.insert(&format!("nick{i}"));
+        }
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(&format!("other{i}")))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+        assert_eq!(filter.stats().maybe as usize, false_positives);
+    }
+}
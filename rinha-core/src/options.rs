@@ -0,0 +1,129 @@
+use std::{env, str::FromStr, time::Duration};
+
+use crate::{CacheLimits, CacheWarmUp, NickFilterConfig, SearchCacheConfig, WriteBehindConfig};
+
+/// Everything tunable about the Postgres repositories of both servers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepositoryOptions {
+    pub cache_limits: CacheLimits,
+    pub cache_warm_up: CacheWarmUp,
+    pub nick_filter: NickFilterConfig,
+    pub search_cache: SearchCacheConfig,
+    /// Batches person creation when set.
+    pub write_behind: Option<WriteBehindConfig>,
+    pub count_mode: CountMode,
+}
+
+impl RepositoryOptions {
+    /// Reads the options from environment variables, leaving the defaults for those unset or
+    /// unparseable.
+    pub fn from_env() -> Self {
+        let cache_limits = CacheLimits {
+            max_entries: env::var("CACHE_MAX_ENTRIES")
+                .ok()
+                .and_then(|max| max.parse::<usize>().ok())
+                .or(CacheLimits::default().max_entries),
+            max_bytes: env::var("CACHE_MAX_BYTES")
+                .ok()
+                .and_then(|max| max.parse::<usize>().ok()),
+        };
+
+        let cache_warm_up = env::var("CACHE_WARM_UP")
+            .ok()
+            .and_then(|warm_up| warm_up.parse::<CacheWarmUp>().ok())
+            .unwrap_or_default();
+
+        let nick_filter = NickFilterConfig {
+            capacity: env::var("NICK_FILTER_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse::<usize>().ok())
+                .unwrap_or(NickFilterConfig::default().capacity),
+            false_positive_rate: env::var("NICK_FILTER_FP_RATE")
+                .ok()
+                .and_then(|rate| rate.parse::<f64>().ok())
+                .unwrap_or(NickFilterConfig::default().false_positive_rate),
+        };
+
+        let search_cache = SearchCacheConfig {
+            ttl: env::var("SEARCH_CACHE_TTL_MS")
+                .ok()
+                .and_then(|ms| ms.parse::<u64>().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(SearchCacheConfig::default().ttl),
+            max_entries: env::var("SEARCH_CACHE_MAX_ENTRIES")
+                .ok()
+                .and_then(|max| max.parse::<usize>().ok())
+                .unwrap_or(SearchCacheConfig::default().max_entries),
+        };
+
+        let write_behind = env::var("WRITE_BEHIND")
+            .is_ok_and(|enabled| enabled == "true")
+            .then(|| WriteBehindConfig {
+                batch_size: env::var("WRITE_BEHIND_BATCH_SIZE")
+                    .ok()
+                    .and_then(|size| size.parse::<usize>().ok())
+                    .unwrap_or(WriteBehindConfig::default().batch_size),
+                flush_interval: env::var("WRITE_BEHIND_FLUSH_MS")
+                    .ok()
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .map(Duration::from_millis)
+                    .unwrap_or(WriteBehindConfig::default().flush_interval),
+            });
+
+        let count_mode = env::var("COUNT_MODE")
+            .ok()
+            .and_then(|mode| mode.parse::<CountMode>().ok())
+            .unwrap_or_default();
+
+        Self {
+            cache_limits,
+            cache_warm_up,
+            nick_filter,
+            search_cache,
+            write_behind,
+            count_mode,
+        }
+    }
+}
+
+/// How `/contagem-pessoas` counts people.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountMode {
+    /// Sums the counter maintained by triggers on `people`.
+    #[default]
+    Exact,
+    /// Uses the row estimate of the planner statistics, which is only as fresh as the last
+    /// `ANALYZE`, but costs nothing.
+    Estimated,
+}
+
+impl CountMode {
+    /// The query counting people, as a single `BIGINT`.
+    pub fn query(self) -> &'static str {
+        match self {
+            CountMode::Exact => "SELECT COALESCE(SUM(count), 0)::BIGINT FROM people_count",
+            // Tables never analyzed are estimated at -1 rows.
+            CountMode::Estimated => {
+                "SELECT GREATEST(reltuples, 0)::BIGINT FROM pg_class WHERE oid = 'people'::REGCLASS"
+            }
+        }
+    }
+
+    /// How many people a count read with [`CountMode::query`] stands for. The counter drifts below
+    /// zero when rows go without firing its triggers, as on `TRUNCATE`, which counts as nobody.
+    pub fn people(count: i64) -> u64 {
+        count.max(0) as u64
+    }
+}
+
+impl FromStr for CountMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "" | "exact" => Ok(Self::Exact),
+            "estimated" => Ok(Self::Estimated),
+            other => Err(format!("unknown count mode: {other}")),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{error::Error, fmt::Display};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{NewPerson, Person, Readiness, RepositoryStats, SearchQuery};
+
+pub type PersistenceResult<T> = Result<T, PersistenceError>;
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    UniqueViolation,
+    DatabaseError(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for PersistenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UniqueViolation => write!(f, "unique constraint violated"),
+            Self::DatabaseError(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PersistenceError {}
+
+#[cfg(feature = "sqlx")]
+impl From<sqlx::Error> for PersistenceError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                PersistenceError::UniqueViolation
+            }
+            _ => PersistenceError::DatabaseError(Box::new(error)),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<postgres::Error> for PersistenceError {
+    fn from(value: postgres::Error) -> Self {
+        match value.code() {
+            Some(&postgres::error::SqlState::UNIQUE_VIOLATION) => PersistenceError::UniqueViolation,
+            _ => PersistenceError::DatabaseError(Box::new(value)),
+        }
+    }
+}
+
+#[cfg(feature = "r2d2")]
+impl From<r2d2::Error> for PersistenceError {
+    fn from(value: r2d2::Error) -> Self {
+        PersistenceError::DatabaseError(Box::new(value))
+    }
+}
+
+/// Blocking storage for people, used by thread-per-request servers.
+pub trait PersonRepository: Send + Sync {
+    fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid>;
+    fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>>;
+    /// Replaces everything about a person, returning `None` when there is nobody with that id.
+    fn update_person(&self, id: Uuid, person: NewPerson) -> PersistenceResult<Option<Person>>;
+    /// Returns whether there was somebody with that id to be removed.
+    fn delete_person(&self, id: Uuid) -> PersistenceResult<bool>;
+    fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>>;
+    fn count_people(&self) -> PersistenceResult<u64>;
+    /// Waits until every accepted person is written, for repositories that defer writes.
+    fn flush(&self) -> PersistenceResult<()> {
+        Ok(())
+    }
+    /// Stops deferring writes and waits until every accepted person is written, on shutdown, as
+    /// people accepted in write-behind mode must not be lost on the way out. People created
+    /// afterwards are written right away.
+    fn close(&self) -> PersistenceResult<()> {
+        self.flush()
+    }
+    /// Pool, cache and listener stats, for repositories that have them.
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats::default()
+    }
+    /// Checks whatever the repository depends on to serve requests.
+    fn readiness(&self) -> Readiness {
+        Readiness::default()
+    }
+}
+
+/// Non-blocking storage for people, used by async servers.
+#[async_trait]
+pub trait AsyncPersonRepository: Send + Sync {
+    async fn create_person(&self, new_person: NewPerson) -> PersistenceResult<Uuid>;
+    async fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>>;
+    /// Replaces everything about a person, returning `None` when there is nobody with that id.
+    async fn update_person(&self, id: Uuid, person: NewPerson)
+        -> PersistenceResult<Option<Person>>;
+    /// Returns whether there was somebody with that id to be removed.
+    async fn delete_person(&self, id: Uuid) -> PersistenceResult<bool>;
+    async fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>>;
+    async fn count_people(&self) -> PersistenceResult<u64>;
+    /// Waits until every accepted person is written, for repositories that defer writes. Servers
+    /// call it once they stopped taking requests, as people accepted in write-behind mode must not
+    /// be lost on the way out.
+    async fn flush(&self) -> PersistenceResult<()> {
+        Ok(())
+    }
+    /// Pool, cache and listener stats, for repositories that have them.
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats::default()
+    }
+    /// Checks whatever the repository depends on to serve requests.
+    async fn readiness(&self) -> Readiness {
+        Readiness::default()
+    }
+}
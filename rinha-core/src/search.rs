@@ -0,0 +1,407 @@
+use std::{env, sync::OnceLock};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+use time::Date;
+use uuid::Uuid;
+
+use crate::{ApiError, ErrorCode, Person, SearchTerm, MAX_SEARCH_RESULTS};
+
+static RULES: OnceLock<SearchRules> = OnceLock::new();
+
+/// Response header with the cursor for the next page of a search.
+pub const NEXT_CURSOR_HEADER: &str = "x-proximo-cursor";
+
+/// How a search term matches people, chosen with the `modo` query parameter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum SearchMode {
+    /// Case insensitive substring of the name, nick or stack, in no particular order.
+    #[default]
+    #[serde(rename = "substring")]
+    Substring,
+    /// Trigram similarity, which tolerates typos, most similar first.
+    #[serde(rename = "trigrama")]
+    Trigram,
+    /// Full-text search with Portuguese stemming, most relevant first.
+    #[serde(rename = "texto")]
+    FullText,
+}
+
+/// What searches are allowed to do, set up once for the whole process.
+#[derive(Debug, Clone, Default)]
+pub struct SearchRules {
+    /// Lets searches without a term or filters list everybody, page by page, so the dataset can
+    /// be browsed.
+    pub allow_listing: bool,
+}
+
+impl SearchRules {
+    /// Makes these the rules used by every search in the process. This should happen at
+    /// startup, as it fails, giving the rules back, once any were installed or used.
+    pub fn install(self) -> Result<(), Self> {
+        RULES.set(self)
+    }
+
+    pub fn current() -> &'static Self {
+        RULES.get_or_init(Self::default)
+    }
+
+    /// Reads the rules from environment variables, leaving the defaults for those unset.
+    pub fn from_env() -> Self {
+        Self {
+            allow_listing: env::var("SEARCH_ALLOW_LISTING").is_ok_and(|allow| allow == "true"),
+        }
+    }
+}
+
+/// Whether people must know any or all of the techs in a stack filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum StackMatch {
+    #[default]
+    #[serde(rename = "qualquer")]
+    Any,
+    #[serde(rename = "todas")]
+    All,
+}
+
+/// The query string of `GET /pessoas`: a search term and filters on specific fields, all of them
+/// combined, along with the page to return.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+pub struct SearchQuery {
+    #[serde(rename = "t")]
+    pub term: Option<SearchTerm>,
+    #[serde(rename = "modo", default)]
+    pub mode: SearchMode,
+    /// Case insensitive substring of the name.
+    #[serde(rename = "nome", default, deserialize_with = "name_term")]
+    pub name: Option<SearchTerm>,
+    /// The exact nick.
+    #[serde(rename = "apelido")]
+    pub nick: Option<String>,
+    /// Comma separated techs, matched regardless of casing.
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub stack: Vec<String>,
+    #[serde(rename = "stack_modo", default)]
+    pub stack_match: StackMatch,
+    #[serde(rename = "nascimento_de", default, with = "crate::date_format::option")]
+    pub born_from: Option<Date>,
+    #[serde(
+        rename = "nascimento_ate",
+        default,
+        with = "crate::date_format::option"
+    )]
+    pub born_until: Option<Date>,
+    /// How many people to return, up to [`MAX_SEARCH_RESULTS`].
+    #[serde(rename = "limite")]
+    pub limit: Option<usize>,
+    /// Id of the last person of the previous page.
+    pub cursor: Option<Uuid>,
+}
+
+impl SearchQuery {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(MAX_SEARCH_RESULTS)
+            .clamp(1, MAX_SEARCH_RESULTS)
+    }
+
+    /// Whether results are ordered by id, oldest first, and so can be paginated. Ranked results
+    /// are not, and only their first page is ever returned.
+    pub fn is_paginated(&self) -> bool {
+        self.term.is_none() || self.mode == SearchMode::Substring
+    }
+
+    /// The cursor for the page after these results, unless they were the last one.
+    pub fn next_cursor(&self, people: &[Person]) -> Option<Uuid> {
+        if !self.is_paginated() || people.len() < self.limit() {
+            return None;
+        }
+        people.last().map(|person| person.id)
+    }
+
+    /// Refuses searches that can't be answered: those without anything to search by, unless
+    /// listing everybody is allowed, and those asking for a later page of ranked results.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if !self.is_paginated() && self.cursor.is_some() {
+            return Err(ApiError {
+                field: Some(String::from("cursor")),
+                ..ApiError::new(
+                    ErrorCode::InvalidParameter,
+                    "cursor can't be used with ranked search modes",
+                )
+            });
+        }
+        if !self.is_allowed() {
+            return Err(ApiError::missing_search_criteria());
+        }
+        Ok(())
+    }
+
+    /// Whether there is anything to search by, or listing everybody is allowed.
+    fn is_allowed(&self) -> bool {
+        self.term.is_some() || self.has_filters() || SearchRules::current().allow_listing
+    }
+
+    fn has_filters(&self) -> bool {
+        self.name.is_some()
+            || self.nick.is_some()
+            || !self.stack.is_empty()
+            || self.born_from.is_some()
+            || self.born_until.is_some()
+    }
+
+    /// The same query, with case insensitive fields lowercased, as [`SearchQuery::matches`]
+    /// expects them.
+    pub(crate) fn to_lowercase(&self) -> Self {
+        Self {
+            term: self.term.as_ref().map(SearchTerm::to_lowercase),
+            name: self.name.as_ref().map(SearchTerm::to_lowercase),
+            ..self.clone()
+        }
+    }
+
+    /// Whether a person matches the term as a substring, along with every filter. The query must
+    /// be lowercased first.
+    pub(crate) fn matches(&self, person: &LowercasePerson) -> bool {
+        self.term
+            .as_ref()
+            .is_none_or(|term| person.search_text.contains(term.as_str()))
+            && self.matches_filters(person)
+    }
+
+    /// Same as [`SearchQuery::matches`], leaving the term out.
+    pub(crate) fn matches_filters(&self, person: &LowercasePerson) -> bool {
+        if let Some(name) = &self.name {
+            if !person.name.contains(name.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(nick) = &self.nick {
+            if person.person.nick.as_str() != nick {
+                return false;
+            }
+        }
+
+        if !self.stack.is_empty() {
+            let knows = |tech: &String| person.stack.contains(tech);
+            let matched = match self.stack_match {
+                StackMatch::Any => self.stack.iter().any(knows),
+                StackMatch::All => self.stack.iter().all(knows),
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        let birth_date = person.person.birth_date;
+        self.born_from.is_none_or(|from| birth_date >= from)
+            && self.born_until.is_none_or(|until| birth_date <= until)
+    }
+}
+
+/// A person with whatever searches match case insensitively lowercased, once, to be matched
+/// against any number of queries.
+pub(crate) struct LowercasePerson<'a> {
+    pub person: &'a Person,
+    pub search_text: String,
+    name: String,
+    stack: Vec<String>,
+}
+
+impl<'a> LowercasePerson<'a> {
+    pub fn new(person: &'a Person) -> Self {
+        Self {
+            person,
+            search_text: person.search_text(),
+            name: person.name.as_str().to_lowercase(),
+            stack: person
+                .stack
+                .iter()
+                .flatten()
+                .map(|tech| tech.to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+/// The name filter, bounded like the search term but blamed on its own parameter.
+fn name_term<'de, D>(deserializer: D) -> Result<Option<SearchTerm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| SearchTerm::parse("nome", name).map_err(D::Error::custom))
+        .transpose()
+}
+
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    Ok(value
+        .split(',')
+        .map(|tech| tech.trim().to_lowercase())
+        .filter(|tech| !tech.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::macros::date;
+
+    use super::*;
+    use crate::{Nick, PersonName};
+
+    fn search(term: &str, mode: SearchMode, cursor: Option<Uuid>) -> SearchQuery {
+        SearchQuery {
+            term: Some(SearchTerm::try_from(term.to_owned()).ok().unwrap()),
+            mode,
+            cursor,
+            ..SearchQuery::default()
+        }
+    }
+
+    #[test]
+    fn cursors_only_page_through_results_ordered_by_id() {
+        let cursor = Some(Uuid::now_v7());
+
+        assert_eq!(
+            search("joao", SearchMode::Substring, cursor).validate(),
+            Ok(())
+        );
+        let filtered = SearchQuery {
+            name: SearchTerm::parse("nome", String::from("joao")).ok(),
+            mode: SearchMode::FullText,
+            cursor,
+            ..SearchQuery::default()
+        };
+        assert_eq!(filtered.validate(), Ok(()));
+
+        for mode in [SearchMode::Trigram, SearchMode::FullText] {
+            assert_eq!(search("joao", mode, None).validate(), Ok(()));
+            let err = search("joao", mode, cursor).validate().unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidParameter);
+            assert_eq!(err.status(), 400);
+        }
+    }
+
+    #[test]
+    fn searches_need_something_to_search_by() {
+        let err = SearchQuery::default().validate().unwrap_err();
+        assert_eq!(err.code, ErrorCode::MissingSearchCriteria);
+    }
+
+    fn query(params: serde_json::Value) -> SearchQuery {
+        serde_json::from_value::<SearchQuery>(params)
+            .unwrap()
+            .to_lowercase()
+    }
+
+    fn person(name: &str, nick: &str, birth_date: Date, stack: &[&str]) -> Person {
+        Person {
+            id: Uuid::nil(),
+            name: PersonName::from_stored(name.to_owned()),
+            nick: Nick::from_stored(nick.to_owned()),
+            birth_date,
+            stack: Some(stack.iter().map(|tech| tech.to_string()).collect()),
+        }
+    }
+
+    fn ana() -> Person {
+        person("Ana Maria", "ana", date!(1990 - 05 - 10), &["Rust", "Go"])
+    }
+
+    fn matches(params: serde_json::Value, person: &Person) -> bool {
+        query(params).matches_filters(&LowercasePerson::new(person))
+    }
+
+    #[test]
+    fn stacks_are_split_on_commas_and_lowercased() {
+        assert_eq!(
+            query(json!({"stack": " Rust, ,GO,,"})).stack,
+            ["rust", "go"]
+        );
+    }
+
+    #[test]
+    fn an_empty_stack_is_no_filter() {
+        let empty = query(json!({"stack": ""}));
+        assert!(empty.stack.is_empty());
+        assert!(empty.matches_filters(&LowercasePerson::new(&ana())));
+        assert_eq!(
+            empty.validate().unwrap_err().code,
+            ErrorCode::MissingSearchCriteria
+        );
+    }
+
+    #[test]
+    fn names_are_matched_as_case_insensitive_substrings() {
+        assert!(matches(json!({"nome": "MARIA"}), &ana()));
+        assert!(matches(json!({"nome": "a m"}), &ana()));
+        assert!(!matches(json!({"nome": "joana"}), &ana()));
+    }
+
+    #[test]
+    fn nicks_are_matched_exactly() {
+        assert!(matches(json!({"apelido": "ana"}), &ana()));
+        assert!(!matches(json!({"apelido": "an"}), &ana()));
+        assert!(!matches(json!({"apelido": "anab"}), &ana()));
+    }
+
+    #[test]
+    fn stacks_match_any_tech_unless_all_are_asked_for() {
+        assert!(matches(json!({"stack": "go,elixir"}), &ana()));
+        assert!(matches(
+            json!({"stack": "go,elixir", "stack_modo": "qualquer"}),
+            &ana()
+        ));
+        assert!(!matches(
+            json!({"stack": "go,elixir", "stack_modo": "todas"}),
+            &ana()
+        ));
+        assert!(matches(
+            json!({"stack": "RUST,go", "stack_modo": "todas"}),
+            &ana()
+        ));
+        assert!(!matches(json!({"stack": "elixir"}), &ana()));
+
+        let no_stack = Person {
+            stack: None,
+            ..ana()
+        };
+        assert!(!matches(json!({"stack": "rust"}), &no_stack));
+    }
+
+    #[test]
+    fn birth_date_ranges_are_inclusive() {
+        assert!(matches(json!({"nascimento_de": "1990-05-10"}), &ana()));
+        assert!(matches(json!({"nascimento_ate": "1990-05-10"}), &ana()));
+        assert!(matches(
+            json!({"nascimento_de": "1990-01-01", "nascimento_ate": "1990-12-31"}),
+            &ana()
+        ));
+        assert!(!matches(json!({"nascimento_de": "1990-05-11"}), &ana()));
+        assert!(!matches(json!({"nascimento_ate": "1990-05-09"}), &ana()));
+    }
+
+    #[test]
+    fn inverted_birth_date_ranges_match_nobody() {
+        let inverted = json!({"nascimento_de": "1991-01-01", "nascimento_ate": "1989-01-01"});
+        assert!(!matches(inverted.clone(), &ana()));
+        assert_eq!(query(inverted).validate(), Ok(()));
+    }
+
+    #[test]
+    fn every_filter_must_match() {
+        assert!(matches(
+            json!({"nome": "ana", "apelido": "ana", "stack": "go", "nascimento_de": "1990-01-01"}),
+            &ana()
+        ));
+        assert!(!matches(
+            json!({"nome": "ana", "apelido": "ana", "stack": "go", "nascimento_de": "2000-01-01"}),
+            &ana()
+        ));
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use uuid::Uuid;
+
+use crate::{search::LowercasePerson, Person, SearchQuery};
+
+const SHARDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchCacheConfig {
+    /// How long results are reused. Besides the change events patching them, this bounds how
+    /// stale they can get.
+    pub ttl: Duration,
+    /// Split evenly between the cache shards. Zero disables the cache.
+    pub max_entries: usize,
+}
+
+impl Default for SearchCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(1),
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+/// Search results cached by query, evicting the least recently used queries once full.
+///
+/// Terms are matched case insensitively, like `ILIKE`, so queries only differing by the casing of
+/// those fields share their results. Created people have the highest ids, so they are added to
+/// the end of unranked first pages that still have room, which hold every result there is. Those
+/// pages are tracked on their own, so creating somebody only goes through them. Results holding
+/// updated or deleted people are dropped, as there is no telling which queries they stopped
+/// matching, and so are those updated people now match. Ranked results otherwise rely on expiring.
+///
+/// Like the person cache, results can only be trusted while changes are arriving, and queries are
+/// spread over independently locked shards, so patching the results of one shard on every change
+/// doesn't hold up searches on the others.
+pub struct SearchCache {
+    /// Empty when the cache is disabled.
+    shards: Vec<Mutex<LruCache<SearchQuery, Results>>>,
+    /// Cached first pages of unranked results that are not full, which is where created people go.
+    open_pages: Mutex<HashSet<SearchQuery>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+struct Results {
+    people: Vec<Person>,
+    expires_at: Instant,
+}
+
+impl SearchCache {
+    pub fn new(config: SearchCacheConfig) -> Self {
+        Self {
+            shards: NonZeroUsize::new(config.max_entries.div_ceil(SHARDS))
+                .map(|max_entries| {
+                    (0..SHARDS)
+                        .map(|_| Mutex::new(LruCache::new(max_entries)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            open_pages: Mutex::new(HashSet::new()),
+            ttl: config.ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, key: &SearchQuery) -> Option<&Mutex<LruCache<SearchQuery, Results>>> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Some(&self.shards[hasher.finish() as usize % self.shards.len()])
+    }
+
+    pub fn get(&self, query: &SearchQuery) -> Option<Vec<Person>> {
+        let key = query.to_lowercase();
+        let mut entries = self.shard(&key)?.lock().unwrap();
+
+        match entries.get(&key) {
+            Some(results) if results.expires_at > Instant::now() => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(results.people.clone())
+            }
+            Some(_) => {
+                entries.pop(&key);
+                drop(entries);
+                self.close_pages([&key]);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, query: &SearchQuery, people: Vec<Person>) {
+        let key = query.to_lowercase();
+        let Some(entries) = self.shard(&key) else {
+            return;
+        };
+        let open = key.cursor.is_none() && key.is_paginated() && people.len() < key.limit();
+
+        let evicted = entries.lock().unwrap().push(
+            key.clone(),
+            Results {
+                people,
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+
+        let mut open_pages = self.open_pages.lock().unwrap();
+        if let Some((evicted, _)) = evicted {
+            open_pages.remove(&evicted);
+        }
+        match open {
+            true => open_pages.insert(key),
+            false => open_pages.remove(&key),
+        };
+    }
+
+    /// Adds a created person to the open first pages it matches. Ranked results are left alone,
+    /// as there is no telling where the person would rank, and so are later pages, which only
+    /// exist when the first one is full.
+    pub fn include(&self, person: &Person) {
+        if self.shards.is_empty() {
+            return;
+        }
+        let person = LowercasePerson::new(person);
+        let matching = self
+            .open_pages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|query| query.matches(&person))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut closed = vec![];
+        for query in matching {
+            let Some(shard) = self.shard(&query) else {
+                continue;
+            };
+            let mut entries = shard.lock().unwrap();
+            let Some(results) = entries.peek_mut(&query) else {
+                closed.push(query);
+                continue;
+            };
+            if results.people.len() < query.limit()
+                && results
+                    .people
+                    .iter()
+                    .all(|cached| cached.id < person.person.id)
+            {
+                results.people.push(person.person.clone());
+            }
+            if results.people.len() >= query.limit() {
+                closed.push(query);
+            }
+        }
+        self.close_pages(&closed);
+    }
+
+    /// Drops every result holding the person.
+    pub fn invalidate(&self, id: &Uuid) {
+        self.invalidate_where(|_, results| results.people.iter().any(|person| person.id == *id));
+    }
+
+    /// Drops every result holding an updated person or that it may now belong to.
+    pub fn invalidate_updated(&self, updated: &Person) {
+        if self.shards.is_empty() {
+            return;
+        }
+        let updated = LowercasePerson::new(updated);
+        self.invalidate_where(|query, results| {
+            results
+                .people
+                .iter()
+                .any(|person| person.id == updated.person.id)
+                || !query.is_paginated()
+                || query.matches(&updated)
+        });
+    }
+
+    fn invalidate_where(&self, stale: impl Fn(&SearchQuery, &Results) -> bool) {
+        for shard in &self.shards {
+            let mut entries = shard.lock().unwrap();
+
+            let stale = entries
+                .iter()
+                .filter(|(query, results)| stale(query, results))
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in &stale {
+                entries.pop(key);
+            }
+            drop(entries);
+            self.close_pages(&stale);
+            self.invalidations
+                .fetch_add(stale.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().clear();
+        }
+        self.open_pages.lock().unwrap().clear();
+    }
+
+    fn close_pages<'a>(&self, queries: impl IntoIterator<Item = &'a SearchQuery>) {
+        let mut open_pages = self.open_pages.lock().unwrap();
+        for query in queries {
+            open_pages.remove(query);
+        }
+    }
+
+    pub fn stats(&self) -> SearchCacheStats {
+        SearchCacheStats {
+            entries: self
+                .shards
+                .iter()
+                .map(|shard| shard.lock().unwrap().len())
+                .sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+    use crate::{Nick, PersonName, SearchMode, SearchTerm};
+
+    fn person(n: u64, name: &str) -> Person {
+        Person {
+            id: Uuid::from_u64_pair(0, n),
+            name: PersonName::from_stored(name.to_owned()),
+            nick: Nick::from_stored(format!("nick{n}")),
+            birth_date: date!(2000 - 01 - 01),
+            stack: None,
+        }
+    }
+
+    fn query(term: &str) -> SearchQuery {
+        SearchQuery {
+            term: Some(SearchTerm::try_from(term.to_owned()).ok().unwrap()),
+            ..SearchQuery::default()
+        }
+    }
+
+    fn cache(ttl: Duration) -> SearchCache {
+        SearchCache::new(SearchCacheConfig {
+            ttl,
+            max_entries: 100,
+        })
+    }
+
+    fn ids(people: Option<Vec<Person>>) -> Option<Vec<u64>> {
+        people.map(|people| {
+            people
+                .iter()
+                .map(|person| person.id.as_u64_pair().1)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn queries_differing_by_case_share_results() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert(&query("Ana"), vec![person(1, "Ana")]);
+        assert_eq!(ids(cache.get(&query("aNA"))), Some(vec![1]));
+        assert_eq!(ids(cache.get(&query("bia"))), None);
+    }
+
+    #[test]
+    fn created_people_join_the_first_pages_they_match() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert(&query("ana"), vec![person(1, "Ana")]);
+        cache.insert(&query("bia"), vec![]);
+
+        cache.include(&person(2, "Ana Maria"));
+        assert_eq!(ids(cache.get(&query("ana"))), Some(vec![1, 2]));
+        assert_eq!(ids(cache.get(&query("bia"))), Some(vec![]));
+    }
+
+    #[test]
+    fn full_and_later_pages_are_left_alone() {
+        let cache = cache(Duration::from_secs(60));
+        let full = SearchQuery {
+            limit: Some(1),
+            ..query("ana")
+        };
+        let later = SearchQuery {
+            cursor: Some(Uuid::from_u64_pair(0, 1)),
+            ..query("ana")
+        };
+        cache.insert(&full, vec![person(1, "Ana")]);
+        cache.insert(&later, vec![]);
+
+        cache.include(&person(2, "Ana"));
+        assert_eq!(ids(cache.get(&full)), Some(vec![1]));
+        assert_eq!(ids(cache.get(&later)), Some(vec![]));
+    }
+
+    #[test]
+    fn pages_stop_growing_once_full() {
+        let cache = cache(Duration::from_secs(60));
+        let two = SearchQuery {
+            limit: Some(2),
+            ..query("ana")
+        };
+        cache.insert(&two, vec![person(1, "Ana")]);
+
+        cache.include(&person(2, "Ana"));
+        cache.include(&person(3, "Ana"));
+        assert_eq!(ids(cache.get(&two)), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn ranked_results_are_left_alone() {
+        let cache = cache(Duration::from_secs(60));
+        let ranked = SearchQuery {
+            mode: SearchMode::Trigram,
+            ..query("ana")
+        };
+        cache.insert(&ranked, vec![]);
+
+        cache.include(&person(1, "Ana"));
+        assert_eq!(ids(cache.get(&ranked)), Some(vec![]));
+    }
+
+    #[test]
+    fn people_older_than_the_results_are_not_appended() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert(&query("ana"), vec![person(2, "Ana")]);
+
+        cache.include(&person(1, "Ana"));
+        cache.include(&person(2, "Ana"));
+        assert_eq!(ids(cache.get(&query("ana"))), Some(vec![2]));
+    }
+
+    #[test]
+    fn results_holding_deleted_people_are_dropped() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert(&query("ana"), vec![person(1, "Ana")]);
+        cache.insert(&query("bia"), vec![person(2, "Bia")]);
+
+        cache.invalidate(&Uuid::from_u64_pair(0, 1));
+        assert_eq!(ids(cache.get(&query("ana"))), None);
+        assert_eq!(ids(cache.get(&query("bia"))), Some(vec![2]));
+        assert_eq!(cache.stats().invalidations, 1);
+
+        // Dropped pages no longer take created people.
+        cache.include(&person(3, "Ana"));
+        assert_eq!(ids(cache.get(&query("ana"))), None);
+    }
+
+    #[test]
+    fn results_an_updated_person_left_or_joined_are_dropped() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert(&query("ana"), vec![person(1, "Ana")]);
+        cache.insert(&query("bia"), vec![]);
+        cache.insert(&query("caio"), vec![]);
+
+        cache.invalidate_updated(&person(1, "Bia"));
+        assert_eq!(ids(cache.get(&query("ana"))), None);
+        assert_eq!(ids(cache.get(&query("bia"))), None);
+        assert_eq!(ids(cache.get(&query("caio"))), Some(vec![]));
+    }
+
+    #[test]
+    fn results_expire() {
+        let cache = cache(Duration::ZERO);
+        cache.insert(&query("ana"), vec![person(1, "Ana")]);
+        assert_eq!(ids(cache.get(&query("ana"))), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 0, 1));
+    }
+
+    #[test]
+    fn a_disabled_cache_keeps_nothing() {
+        let cache = SearchCache::new(SearchCacheConfig {
+            ttl: Duration::from_secs(60),
+            max_entries: 0,
+        });
+        cache.insert(&query("ana"), vec![]);
+        cache.include(&person(1, "Ana"));
+        assert_eq!(ids(cache.get(&query("ana"))), None);
+    }
+}
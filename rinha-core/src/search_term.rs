@@ -0,0 +1,164 @@
+use serde::Deserialize;
+
+use crate::{ValidationError, ValidationRule};
+
+/// Shorter terms match almost everybody and can't be served by the trigram index.
+pub const MIN_SEARCH_TERM_LENGTH: usize = 2;
+/// As long as the longest name, which is plenty for anything worth searching for.
+pub const MAX_SEARCH_TERM_LENGTH: usize = 100;
+
+/// The `t` parameter of a search, or another text to search by, cleaned up and checked before it
+/// gets anywhere near a query.
+///
+/// Surrounding whitespace is trimmed and inner runs of it, tabs and newlines included, collapse
+/// into a single space, so a term padded with thousands of spaces is no different from the plain
+/// one. Other control characters are rejected, and lengths are counted in characters once cleaned
+/// up.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SearchTerm(String);
+
+impl SearchTerm {
+    /// Cleans up and checks a search text, blaming `field` when it is refused.
+    pub fn parse(field: &str, value: String) -> Result<Self, ValidationError> {
+        if value.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+            return Err(ValidationError::new(field, ValidationRule::InvalidFormat));
+        }
+
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        match value.chars().count() {
+            0 => Err(ValidationError::new(field, ValidationRule::Blank)),
+            length if length < MIN_SEARCH_TERM_LENGTH => Err(ValidationError::new(
+                field,
+                ValidationRule::MinLength {
+                    min: MIN_SEARCH_TERM_LENGTH,
+                },
+            )),
+            length if length > MAX_SEARCH_TERM_LENGTH => Err(ValidationError::new(
+                field,
+                ValidationRule::MaxLength {
+                    max: MAX_SEARCH_TERM_LENGTH,
+                },
+            )),
+            _ => Ok(Self(value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// An `ILIKE` pattern matching the term anywhere, taking `%`, `_` and `\` literally.
+    pub fn contains_pattern(&self) -> String {
+        contains_pattern(&self.0)
+    }
+
+    pub(crate) fn to_lowercase(&self) -> Self {
+        Self(self.0.to_lowercase())
+    }
+}
+
+impl TryFrom<String> for SearchTerm {
+    type Error = ValidationError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse("t", value)
+    }
+}
+
+/// An `ILIKE` pattern matching the text anywhere, with its wildcards and escape character
+/// escaped by the default `\`.
+pub fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryRepository, NewPerson, PersonRepository, SearchQuery};
+
+    fn rule(value: &str) -> Option<ValidationRule> {
+        SearchTerm::try_from(value.to_owned())
+            .err()
+            .map(|err| err.rule)
+    }
+
+    #[test]
+    fn wildcards_and_the_escape_character_are_taken_literally() {
+        assert_eq!(contains_pattern("%_\\"), "%\\%\\_\\\\%");
+        assert_eq!(contains_pattern("joão"), "%joão%");
+    }
+
+    #[test]
+    fn whitespace_is_collapsed_before_counting() {
+        let term = SearchTerm::try_from(format!("{}joão   da  silva ", " ".repeat(5_000)))
+            .ok()
+            .unwrap();
+        assert_eq!(term.as_str(), "joão da silva");
+        assert_eq!(rule("   "), Some(ValidationRule::Blank));
+
+        let term = SearchTerm::try_from(String::from("joão\tda\r\n silva"))
+            .ok()
+            .unwrap();
+        assert_eq!(term.as_str(), "joão da silva");
+    }
+
+    #[test]
+    fn lengths_are_counted_in_characters() {
+        assert_eq!(
+            rule("a"),
+            Some(ValidationRule::MinLength {
+                min: MIN_SEARCH_TERM_LENGTH
+            })
+        );
+        assert_eq!(rule("ão"), None);
+        assert_eq!(rule(&"ç".repeat(MAX_SEARCH_TERM_LENGTH)), None);
+        assert_eq!(
+            rule(&"ç".repeat(MAX_SEARCH_TERM_LENGTH + 1)),
+            Some(ValidationRule::MaxLength {
+                max: MAX_SEARCH_TERM_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn control_characters_are_refused() {
+        for term in ["jo\u{0}ao", "joao\u{7f}", "jo\u{1b}[2Jao"] {
+            assert_eq!(rule(term), Some(ValidationRule::InvalidFormat));
+        }
+    }
+
+    #[test]
+    fn wildcards_do_not_match_everybody() {
+        let repo = InMemoryRepository::new();
+        for (name, nick) in [("João", "joao"), ("Maria 100%_", "maria")] {
+            let json =
+                format!(r#"{{"nome": "{name}", "apelido": "{nick}", "nascimento": "2000-01-01"}}"#);
+            let person = NewPerson::from_json(json.as_bytes()).ok().unwrap();
+            PersonRepository::create_person(&repo, person).unwrap();
+        }
+
+        let query = serde_json::from_str::<SearchQuery>(r#"{"t": "%_"}"#).unwrap();
+        let people = PersonRepository::search_people(&repo, &query).unwrap();
+        let nicks = people
+            .iter()
+            .map(|person| person.nick.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(nicks, ["maria"]);
+    }
+
+    #[test]
+    fn refusals_name_the_parameter() {
+        let err = SearchTerm::parse("nome", String::from("a")).err().unwrap();
+        assert_eq!(err.field, "nome");
+    }
+}
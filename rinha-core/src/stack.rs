@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+
+use crate::Tech;
+
+/// Maximum number of entries accepted on a person `stack`.
+pub const MAX_STACK_ENTRIES: usize = 32;
+
+impl Tech {
+    /// Key used to decide whether two techs are the same one, regardless of casing.
+    pub fn key(&self) -> String {
+        self.as_str().to_lowercase()
+    }
+}
+
+/// Prepares a stack to be stored, dropping techs that only differ by casing from a previous one.
+///
+/// The first spelling of each tech is the one kept, as is the order they were informed in.
+pub fn normalize_stack(stack: impl IntoIterator<Item = Tech>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    stack
+        .into_iter()
+        .filter(|tech| seen.insert(tech.key()))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(techs: &[&str]) -> Vec<Tech> {
+        techs
+            .iter()
+            .map(|tech| Tech::try_from(tech.to_string()).ok().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn techs_differing_by_casing_keep_their_first_spelling() {
+        assert_eq!(
+            normalize_stack(stack(&["Rust", "rust", "NODE", "RUST", "Node"])),
+            ["Rust", "NODE"]
+        );
+    }
+
+    #[test]
+    fn techs_keep_the_order_they_were_informed_in() {
+        assert_eq!(
+            normalize_stack(stack(&["Python", "C#", "python", "Go", "c#", "Elixir"])),
+            ["Python", "C#", "Go", "Elixir"]
+        );
+        assert!(normalize_stack(stack(&[])).is_empty());
+    }
+}
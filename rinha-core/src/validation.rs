@@ -0,0 +1,130 @@
+use std::{error::Error, fmt::Display};
+
+use serde::Serialize;
+
+/// Describes which field of a payload was rejected and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationError {
+    #[serde(rename = "campo")]
+    pub field: String,
+    #[serde(flatten)]
+    pub rule: ValidationRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "regra")]
+pub enum ValidationRule {
+    #[serde(rename = "obrigatorio")]
+    Required,
+    #[serde(rename = "formato_invalido")]
+    InvalidFormat,
+    #[serde(rename = "data_futura")]
+    FutureDate,
+    #[serde(rename = "ano_minimo")]
+    MinYear {
+        #[serde(rename = "minimo")]
+        min: i32,
+    },
+    #[serde(rename = "idade_minima")]
+    MinAge {
+        #[serde(rename = "minimo")]
+        min: u8,
+    },
+    #[serde(rename = "maximo_itens")]
+    MaxEntries {
+        #[serde(rename = "maximo")]
+        max: usize,
+    },
+    #[serde(rename = "em_branco")]
+    Blank,
+    #[serde(rename = "tamanho_minimo")]
+    MinLength {
+        #[serde(rename = "minimo")]
+        min: usize,
+    },
+    #[serde(rename = "tamanho_maximo")]
+    MaxLength {
+        #[serde(rename = "maximo")]
+        max: usize,
+    },
+}
+
+impl ValidationError {
+    pub fn new(field: impl Into<String>, rule: ValidationRule) -> Self {
+        Self {
+            field: field.into(),
+            rule,
+        }
+    }
+
+    /// Reports the same rule violation under another field name, e.g. `stack[2]` instead of
+    /// `stack`.
+    pub fn at(self, field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            ..self
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.rule {
+            ValidationRule::Required => write!(f, "{} is required", self.field),
+            ValidationRule::InvalidFormat => write!(f, "{} has an invalid format", self.field),
+            ValidationRule::FutureDate => write!(f, "{} must not be in the future", self.field),
+            ValidationRule::MinYear { min } => {
+                write!(f, "{} must not be before the year {}", self.field, min)
+            }
+            ValidationRule::MinAge { min } => {
+                write!(f, "{} must be at least {} years ago", self.field, min)
+            }
+            ValidationRule::MaxEntries { max } => {
+                write!(f, "{} must have at most {} entries", self.field, max)
+            }
+            ValidationRule::Blank => write!(f, "{} must not be blank", self.field),
+            ValidationRule::MinLength { min } => {
+                write!(f, "{} must have at least {} characters", self.field, min)
+            }
+            ValidationRule::MaxLength { max } => {
+                write!(f, "{} must have at most {} characters", self.field, max)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Why a request body could not be turned into a valid value.
+#[derive(Debug)]
+pub enum PayloadError {
+    /// The body is not JSON or some field has the wrong type, like `"nome": 1` or `"stack": [1]`.
+    /// Servers should answer with 400.
+    Syntax(serde_json::Error),
+    /// The body is well formed but breaks a rule, like a missing `nome` or a too long `apelido`.
+    /// Servers should answer with 422.
+    Invalid(ValidationError),
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax(err) => write!(f, "{}", err),
+            Self::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PayloadError {}
+
+impl From<serde_json::Error> for PayloadError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Syntax(err)
+    }
+}
+
+impl From<ValidationError> for PayloadError {
+    fn from(err: ValidationError) -> Self {
+        Self::Invalid(err)
+    }
+}
@@ -0,0 +1,378 @@
+use std::time::Duration;
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use uuid::Uuid;
+
+use crate::{Backoff, Person};
+
+/// Postgres takes at most 65535 parameters per statement, and each person needs five.
+const MAX_WRITE_BEHIND_BATCH: usize = 10_000;
+
+/// How many times a part of a batch is tried while the database can't be reached, about forty
+/// seconds with the default [`Backoff`], before its people are given up on.
+pub const MAX_BATCH_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriteBehindConfig {
+    /// A batch is written as soon as it has this many people.
+    pub batch_size: usize,
+    /// Otherwise, whatever is queued is written this often.
+    pub flush_interval: Duration,
+}
+
+impl Default for WriteBehindConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            flush_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+impl WriteBehindConfig {
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.clamp(1, MAX_WRITE_BEHIND_BATCH)
+    }
+}
+
+/// People accepted but not yet written to the database.
+///
+/// Their nicks stay reserved until they are written, so two of them can't take the same one, and
+/// they can be found by id in the meantime. Only the nick is written to the database right away,
+/// so a taken one is refused up front rather than lost with the batch. Searches and counts only
+/// see them once written.
+#[derive(Default)]
+pub struct PendingPeople {
+    people: DashMap<Uuid, Person>,
+    nicks: DashMap<String, Uuid>,
+}
+
+impl PendingPeople {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `false`, keeping nothing, when another pending person already has the nick.
+    pub fn reserve(&self, person: &Person) -> bool {
+        match self.nicks.entry(person.nick.as_str().to_owned()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(person.id);
+                self.people.insert(person.id, person.clone());
+                true
+            }
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Person> {
+        self.people.get(id).map(|person| person.clone())
+    }
+
+    /// Forgets people once their batch was written.
+    pub fn release<'a>(&self, people: impl IntoIterator<Item = &'a Person>) {
+        for person in people {
+            self.people.remove(&person.id);
+            self.nicks.remove(person.nick.as_str());
+        }
+    }
+}
+
+/// Why writing a part of a batch failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteFailure {
+    /// The database couldn't be reached, or asked to be retried. The same rows may well go in
+    /// on the next attempt.
+    Transient,
+    /// The database refused the rows, and will keep refusing at least one of them.
+    Permanent,
+}
+
+impl WriteFailure {
+    /// Classifies an error by its SQLSTATE, `None` meaning it never got an answer from the
+    /// server. Connection exceptions, lack of resources, operator interventions such as a
+    /// shutdown and serialization failures are worth retrying; anything else is about the rows.
+    pub fn from_sqlstate(code: Option<&str>) -> Self {
+        match code {
+            None => WriteFailure::Transient,
+            Some(code)
+                if ["08", "40", "53", "57"]
+                    .iter()
+                    .any(|class| code.starts_with(class)) =>
+            {
+                WriteFailure::Transient
+            }
+            Some(_) => WriteFailure::Permanent,
+        }
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl From<&sqlx::Error> for WriteFailure {
+    fn from(error: &sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(err) => Self::from_sqlstate(err.code().as_deref()),
+            _ => WriteFailure::Transient,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<&postgres::Error> for WriteFailure {
+    fn from(error: &postgres::Error) -> Self {
+        Self::from_sqlstate(error.code().map(postgres::error::SqlState::code))
+    }
+}
+
+/// Writes a batch of people accepted in write-behind mode, who were all told they were created.
+///
+/// The whole batch goes in with one statement while that works. Parts that can't be written
+/// because of the database are retried up to [`MAX_BATCH_ATTEMPTS`] times, and parts refused
+/// because of their rows are split in halves until the people at fault are on their own, so
+/// only those are dropped. This drives the writes without doing them, so both servers share it.
+pub struct BatchWrite {
+    parts: Vec<Vec<Person>>,
+    attempts: u32,
+    backoff: Backoff,
+    settled: Vec<Person>,
+    dropped: Vec<Person>,
+}
+
+/// What became of a batch once every part of it was settled.
+#[derive(Default)]
+pub struct BatchOutcome {
+    /// Everybody in the batch, who no longer has to stay pending, as written rows hold their nicks
+    /// from now on.
+    pub settled: Vec<Person>,
+    /// Those who could not be written, whose nick reservations have to go.
+    pub dropped: Vec<Person>,
+}
+
+impl BatchWrite {
+    pub fn new(batch: Vec<Person>) -> Self {
+        let parts = if batch.is_empty() {
+            vec![]
+        } else {
+            vec![batch]
+        };
+        Self {
+            parts,
+            attempts: 0,
+            backoff: Backoff::default(),
+            settled: vec![],
+            dropped: vec![],
+        }
+    }
+
+    /// The people to write next, or `None` once the whole batch is settled.
+    pub fn next_part(&self) -> Option<&[Person]> {
+        self.parts.last().map(Vec::as_slice)
+    }
+
+    /// Records that the next part was written. People missing from `written` were skipped on a
+    /// conflict, and are dropped.
+    pub fn written(&mut self, written: &[Uuid]) {
+        let Some(part) = self.parts.pop() else {
+            return;
+        };
+        for person in part {
+            if !written.contains(&person.id) {
+                self.dropped.push(person.clone());
+            }
+            self.settled.push(person);
+        }
+        self.start_next_part();
+    }
+
+    /// Records that writing the next part failed. Returns how long to wait before writing it
+    /// again, or `None` when it was split or dropped instead.
+    pub fn failed(&mut self, failure: WriteFailure) -> Option<Duration> {
+        let part = self.parts.pop()?;
+        match failure {
+            WriteFailure::Transient if self.attempts + 1 < MAX_BATCH_ATTEMPTS => {
+                self.attempts += 1;
+                self.parts.push(part);
+                return Some(self.backoff.next_delay());
+            }
+            WriteFailure::Permanent if part.len() > 1 => {
+                let mut first = part;
+                let second = first.split_off(first.len() / 2);
+                self.parts.push(second);
+                self.parts.push(first);
+            }
+            _ => {
+                self.dropped.extend(part.iter().cloned());
+                self.settled.extend(part);
+            }
+        }
+        self.start_next_part();
+        None
+    }
+
+    pub fn finish(self) -> BatchOutcome {
+        BatchOutcome {
+            settled: self.settled,
+            dropped: self.dropped,
+        }
+    }
+
+    fn start_next_part(&mut self) {
+        self.attempts = 0;
+        self.backoff.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+    use crate::{Nick, PersonName};
+
+    fn person(n: u64, nick: &str) -> Person {
+        Person {
+            id: Uuid::from_u64_pair(0, n),
+            name: PersonName::from_stored(format!("Person {n}")),
+            nick: Nick::from_stored(nick.to_owned()),
+            birth_date: date!(2000 - 01 - 01),
+            stack: None,
+        }
+    }
+
+    fn people(count: u64) -> Vec<Person> {
+        (0..count).map(|n| person(n, &format!("nick{n}"))).collect()
+    }
+
+    /// Runs a batch against a database that refuses everybody in `refused` and is unreachable
+    /// for the first `outage` attempts, returning the outcome and the number of attempts.
+    fn write(batch: Vec<Person>, refused: &[Uuid], mut outage: u32) -> (BatchOutcome, u32) {
+        let mut write = BatchWrite::new(batch);
+        let mut attempts = 0;
+        while let Some(part) = write.next_part() {
+            attempts += 1;
+            if outage > 0 {
+                outage -= 1;
+                write.failed(WriteFailure::Transient);
+            } else if part.iter().any(|person| refused.contains(&person.id)) {
+                assert_eq!(write.failed(WriteFailure::Permanent), None);
+            } else {
+                let ids: Vec<_> = part.iter().map(|person| person.id).collect();
+                write.written(&ids);
+            }
+        }
+        (write.finish(), attempts)
+    }
+
+    fn ids(people: &[Person]) -> Vec<Uuid> {
+        let mut ids: Vec<_> = people.iter().map(|person| person.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn pending_people_keep_their_nicks_until_released() {
+        let pending = PendingPeople::new();
+        let ana = person(1, "ana");
+        assert!(pending.reserve(&ana));
+        assert!(!pending.reserve(&person(2, "ana")));
+        assert_eq!(pending.get(&ana.id).map(|found| found.id), Some(ana.id));
+        assert!(pending.get(&person(2, "ana").id).is_none());
+
+        pending.release([&ana]);
+        assert!(pending.get(&ana.id).is_none());
+        assert!(pending.reserve(&person(2, "ana")));
+    }
+
+    #[test]
+    fn releasing_somebody_else_keeps_a_reservation() {
+        let pending = PendingPeople::new();
+        let ana = person(1, "ana");
+        assert!(pending.reserve(&ana));
+        pending.release([&person(2, "bia")]);
+        assert!(pending.get(&ana.id).is_some());
+        assert!(!pending.reserve(&person(3, "ana")));
+    }
+
+    #[test]
+    fn a_batch_that_goes_in_is_written_at_once() {
+        let (outcome, attempts) = write(people(8), &[], 0);
+        assert_eq!(attempts, 1);
+        assert_eq!(outcome.settled.len(), 8);
+        assert!(outcome.dropped.is_empty());
+    }
+
+    #[test]
+    fn an_empty_batch_writes_nothing() {
+        let (outcome, attempts) = write(vec![], &[], 0);
+        assert_eq!(attempts, 0);
+        assert!(outcome.settled.is_empty());
+    }
+
+    #[test]
+    fn people_skipped_on_conflicts_are_dropped() {
+        let batch = people(3);
+        let mut write = BatchWrite::new(batch.clone());
+        write.written(&[batch[0].id, batch[2].id]);
+        assert!(write.next_part().is_none());
+
+        let outcome = write.finish();
+        assert_eq!(outcome.settled.len(), 3);
+        assert_eq!(ids(&outcome.dropped), vec![batch[1].id]);
+    }
+
+    #[test]
+    fn only_refused_people_are_dropped() {
+        let batch = people(9);
+        let refused = [batch[3].id, batch[7].id];
+        let (outcome, _) = write(batch, &refused, 0);
+        assert_eq!(outcome.settled.len(), 9);
+        assert_eq!(ids(&outcome.dropped), ids(&[person(3, ""), person(7, "")]));
+    }
+
+    #[test]
+    fn a_short_outage_is_ridden_out() {
+        let (outcome, attempts) = write(people(4), &[], MAX_BATCH_ATTEMPTS - 1);
+        assert_eq!(attempts, MAX_BATCH_ATTEMPTS);
+        assert!(outcome.dropped.is_empty());
+    }
+
+    #[test]
+    fn a_long_outage_gives_up_on_the_batch() {
+        let (outcome, attempts) = write(people(4), &[], MAX_BATCH_ATTEMPTS);
+        assert_eq!(attempts, MAX_BATCH_ATTEMPTS);
+        assert_eq!(outcome.dropped.len(), 4);
+        assert_eq!(outcome.settled.len(), 4);
+    }
+
+    #[test]
+    fn retries_wait_longer_each_time() {
+        let mut write = BatchWrite::new(people(1));
+        let first = write.failed(WriteFailure::Transient).unwrap();
+        let second = write.failed(WriteFailure::Transient).unwrap();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn failures_are_classified_by_sqlstate() {
+        assert_eq!(WriteFailure::from_sqlstate(None), WriteFailure::Transient);
+        assert_eq!(
+            WriteFailure::from_sqlstate(Some("08006")),
+            WriteFailure::Transient
+        );
+        assert_eq!(
+            WriteFailure::from_sqlstate(Some("57P01")),
+            WriteFailure::Transient
+        );
+        assert_eq!(
+            WriteFailure::from_sqlstate(Some("40001")),
+            WriteFailure::Transient
+        );
+        assert_eq!(
+            WriteFailure::from_sqlstate(Some("23505")),
+            WriteFailure::Permanent
+        );
+        assert_eq!(
+            WriteFailure::from_sqlstate(Some("22021")),
+            WriteFailure::Permanent
+        );
+    }
+}
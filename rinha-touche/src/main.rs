@@ -1,22 +1,44 @@
-use std::{env, io, net::SocketAddr, sync::Arc};
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    os::unix::net::UnixStream,
+    process,
+    sync::Arc,
+    thread,
+    time::Instant,
+};
 
-use persistence::PersistenceError;
-use rinha_core::NewPerson;
-use serde::Deserialize;
+use rinha_core::{
+    ApiError, BirthDateRules, ErrorCode, InMemoryRepository, LogFormat, Metrics, NewPerson,
+    PersistenceError, PersonRepository, RepositoryOptions, SearchQuery, SearchRules,
+    DEFAULT_LOG_FILTER, METRICS_CONTENT_TYPE, NEXT_CURSOR_HEADER,
+};
 use touche::{Body, HttpBody, Method, Request, Response, Server, StatusCode};
+use tracing::{error, info, info_span};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
 use crate::persistence::PostgresRepository;
 
 mod persistence;
 
-#[derive(Deserialize)]
-struct PersonSearchQuery {
-    #[serde(rename = "t")]
-    query: String,
-}
+/// Where every request is logged, so it can be filtered apart from everything else.
+const REQUEST_LOG_TARGET: &str = "rinha_touche::requests";
 
 fn main() -> io::Result<()> {
+    let log_format = env::var("LOG_FORMAT")
+        .ok()
+        .and_then(|format| format.parse::<LogFormat>().ok())
+        .unwrap_or_default();
+    let logger = tracing_subscriber::fmt().with_env_filter(
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER)),
+    );
+    match log_format {
+        LogFormat::Pretty => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+
     let port = env::var("PORT")
         .ok()
         .and_then(|port| port.parse::<u16>().ok())
@@ -35,92 +57,329 @@ fn main() -> io::Result<()> {
         .and_then(|port| port.parse::<usize>().ok())
         .unwrap_or(400);
 
-    let repo = PostgresRepository::connect(&database_url, database_pool_size).unwrap();
-    let repo = Arc::new(repo);
+    BirthDateRules::from_env()
+        .install()
+        .expect("birth date rules are installed once");
+    SearchRules::from_env()
+        .install()
+        .expect("search rules are installed once");
+
+    let repo: Arc<dyn PersonRepository> = match env::var("PERSISTENCE").as_deref() {
+        Ok("memory") => Arc::new(InMemoryRepository::new()),
+        _ => {
+            let repo = PostgresRepository::connect(
+                &database_url,
+                database_pool_size,
+                RepositoryOptions::from_env(),
+            )
+            .expect("DATABASE_URL and DATABASE_POOL are valid");
+            repo.wait_for_warm_up();
+            Arc::new(repo)
+        }
+    };
+
+    thread::spawn({
+        let repo = repo.clone();
+        move || {
+            wait_for_shutdown().unwrap();
+            if let Err(err) = repo.close() {
+                error!(error = %err, "failed to write people on shutdown");
+            }
+            process::exit(0);
+        }
+    });
+
+    let metrics = Arc::new(Metrics::new());
 
     Server::builder()
         .max_threads(max_threads)
         .bind(SocketAddr::from(([0, 0, 0, 0], port)))
-        .serve(move |req: Request<Body>| {
-            let repo = repo.clone();
-            let segments = req.uri().path().split('/').skip(1).collect::<Vec<_>>();
-
-            match (req.method(), segments.as_slice()) {
-                (&Method::GET, ["pessoas"]) => {
-                    let query = req.uri().query().unwrap_or_default();
-                    match serde_urlencoded::from_str::<PersonSearchQuery>(query) {
-                        Ok(PersonSearchQuery { query }) => match repo.search_people(&query) {
-                            Ok(people) => {
-                                let people = serde_json::to_vec(&people).unwrap();
-                                Response::builder()
-                                    .status(StatusCode::OK)
-                                    .header("content-type", "application/json")
-                                    .body(Body::from(people))
-                            }
-                            Err(_) => Response::builder()
-                                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                                .body(Body::empty()),
-                        },
-                        Err(_) => Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::empty()),
-                    }
-                }
+        .serve(move |mut req: Request<Body>| {
+            let span = info_span!(
+                target: REQUEST_LOG_TARGET,
+                "request",
+                method = %req.method(),
+                path = req.uri().path(),
+            );
+            let _entered = span.enter();
+            let method = req.method().clone();
+            let route = route(req.uri().path());
+            let started = Instant::now();
 
-                (&Method::POST, ["pessoas"]) => {
-                    let body = req.into_body();
-                    match serde_json::from_reader::<_, NewPerson>(body.into_reader()) {
-                        Ok(person) => match repo.create_person(person) {
-                            Ok(id) => Response::builder()
-                                .status(StatusCode::CREATED)
-                                .header("location", format!("/pessoas/{id}"))
-                                .body(Body::empty()),
-                            Err(PersistenceError::UniqueViolation) => Response::builder()
-                                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                                .body(Body::empty()),
-                            Err(_) => Response::builder()
-                                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                .body(Body::empty()),
-                        },
-                        Err(_) => Response::builder()
-                            .status(StatusCode::UNPROCESSABLE_ENTITY)
-                            .body(Body::empty()),
-                    }
+            // Like axum, HEAD is answered wherever GET is, with the same headers and no body.
+            let head = req.method() == Method::HEAD;
+            if head {
+                *req.method_mut() = Method::GET;
+            }
+            let res = handle(repo.as_ref(), &metrics, route, req).map(|res| match head {
+                true => res.map(|_| Body::empty()),
+                false => res,
+            });
+            let elapsed = started.elapsed();
+            if let Ok(res) = &res {
+                metrics.record(route, method.as_str(), res.status().as_u16(), elapsed);
+            }
+            let latency_us = elapsed.as_micros() as u64;
+            match &res {
+                Ok(res) if res.status().is_server_error() => {
+                    error!(
+                        target: REQUEST_LOG_TARGET,
+                        status = res.status().as_u16(),
+                        latency_us,
+                        "response failed"
+                    )
+                }
+                Ok(res) => {
+                    info!(
+                        target: REQUEST_LOG_TARGET,
+                        status = res.status().as_u16(),
+                        latency_us,
+                        "finished processing request"
+                    )
                 }
+                Err(err) => error!(
+                    target: REQUEST_LOG_TARGET,
+                    error = %err,
+                    latency_us,
+                    "failed to build response"
+                ),
+            }
+            res
+        })
+}
 
-                (&Method::GET, ["pessoas", id]) => match Uuid::parse_str(id) {
-                    Ok(id) => match repo.find_person(id) {
-                        Ok(Some(person)) => {
-                            let person = serde_json::to_vec(&person).unwrap();
-                            Response::builder()
-                                .status(StatusCode::OK)
-                                .header("content-type", "application/json")
-                                .body(Body::from(person))
+fn handle(
+    repo: &dyn PersonRepository,
+    metrics: &Metrics,
+    route: Option<&str>,
+    req: Request<Body>,
+) -> HttpResult {
+    // The last segment, on routes that take an id.
+    let id = req.uri().path().rsplit('/').next().unwrap_or_default();
+
+    match (req.method(), route) {
+        (&Method::GET, Some("/pessoas")) => {
+            let query =
+                serde_urlencoded::from_str::<SearchQuery>(req.uri().query().unwrap_or_default())
+                    .map_err(|err| ApiError::new(ErrorCode::InvalidParameter, err.to_string()))
+                    .and_then(|query| query.validate().map(|()| query));
+            match query {
+                Ok(query) => match repo.search_people(&query) {
+                    Ok(people) => {
+                        let mut res = Response::builder()
+                            .status(StatusCode::OK)
+                            .header("content-type", "application/json");
+                        if let Some(cursor) = query.next_cursor(&people) {
+                            res = res.header(NEXT_CURSOR_HEADER, cursor.to_string());
                         }
-                        Ok(None) => Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::empty()),
-                        Err(_) => Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Body::empty()),
-                    },
-                    Err(_) => Response::builder()
-                        .status(StatusCode::NOT_FOUND)
+                        let people = serde_json::to_vec(&people).unwrap();
+                        res.body(Body::from(people))
+                    }
+                    Err(err) => persistence_error(err),
+                },
+                Err(err) => error_response(err),
+            }
+        }
+
+        (&Method::POST, Some("/pessoas")) => {
+            let body = req.into_body();
+            match NewPerson::from_reader(body.into_reader()) {
+                Ok(person) => match repo.create_person(person) {
+                    Ok(id) => Response::builder()
+                        .status(StatusCode::CREATED)
+                        .header("location", format!("/pessoas/{id}"))
                         .body(Body::empty()),
+                    Err(err) => persistence_error(err),
                 },
+                Err(err) => error_response(err.into()),
+            }
+        }
 
-                (&Method::GET, ["contagem-pessoas"]) => match repo.count_people() {
-                    Ok(count) => Response::builder()
+        (&Method::GET, Some("/pessoas/:id")) => match Uuid::parse_str(id) {
+            Ok(id) => match repo.find_person(id) {
+                Ok(Some(person)) => {
+                    let person = serde_json::to_vec(&person).unwrap();
+                    Response::builder()
                         .status(StatusCode::OK)
-                        .body(Body::from(count.to_string())),
-                    Err(_) => Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::empty()),
+                        .header("content-type", "application/json")
+                        .body(Body::from(person))
+                }
+                Ok(None) => error_response(ApiError::not_found()),
+                Err(err) => persistence_error(err),
+            },
+            Err(_) => error_response(ApiError::not_found()),
+        },
+
+        (&Method::PUT, Some("/pessoas/:id")) => match Uuid::parse_str(id) {
+            Ok(id) => match NewPerson::from_reader(req.into_body().into_reader()) {
+                Ok(person) => save_person(repo, id, person),
+                Err(err) => error_response(err.into()),
+            },
+            Err(_) => error_response(ApiError::not_found()),
+        },
+
+        (&Method::PATCH, Some("/pessoas/:id")) => match Uuid::parse_str(id) {
+            Ok(id) => match repo.find_person(id) {
+                Ok(Some(current)) => match req.into_body().into_bytes() {
+                    Ok(body) => match NewPerson::from_patch(&current, &body) {
+                        Ok(person) => save_person(repo, id, person),
+                        Err(err) => error_response(err.into()),
+                    },
+                    Err(err) => {
+                        error_response(ApiError::new(ErrorCode::MalformedRequest, err.to_string()))
+                    }
                 },
+                Ok(None) => error_response(ApiError::not_found()),
+                Err(err) => persistence_error(err),
+            },
+            Err(_) => error_response(ApiError::not_found()),
+        },
 
-                _ => Response::builder()
-                    .status(StatusCode::NOT_FOUND)
+        (&Method::DELETE, Some("/pessoas/:id")) => match Uuid::parse_str(id) {
+            Ok(id) => match repo.delete_person(id) {
+                Ok(true) => Response::builder()
+                    .status(StatusCode::NO_CONTENT)
                     .body(Body::empty()),
-            }
-        })
+                Ok(false) => error_response(ApiError::not_found()),
+                Err(err) => persistence_error(err),
+            },
+            Err(_) => error_response(ApiError::not_found()),
+        },
+
+        (&Method::GET, Some("/contagem-pessoas")) => match repo.count_people() {
+            Ok(count) => Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from(count.to_string())),
+            Err(err) => persistence_error(err),
+        },
+
+        (&Method::GET, Some("/health/live")) => Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::empty()),
+
+        (&Method::GET, Some("/health/ready")) => {
+            let readiness = repo.readiness();
+            Response::builder()
+                .status(readiness.status())
+                .header("content-type", "application/json")
+                .body(Body::from(readiness.to_json()))
+        }
+
+        (&Method::GET, Some("/metrics")) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", METRICS_CONTENT_TYPE)
+            .body(Body::from(metrics.render(&repo.stats()))),
+
+        (_, Some(route)) => {
+            let err = ApiError::method_not_allowed();
+            Response::builder()
+                .status(err.status())
+                .header("allow", allowed_methods(route))
+                .header("content-type", "application/json")
+                .body(Body::from(err.to_json()))
+        }
+
+        (_, None) => error_response(ApiError::new(ErrorCode::NotFound, "no such route")),
+    }
+}
+
+/// Blocks until the process is asked to stop with SIGINT or SIGTERM.
+fn wait_for_shutdown() -> io::Result<()> {
+    let (mut receiver, sender) = UnixStream::pair()?;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let sender = sender.try_clone()?;
+        // SAFETY: writing to a socket is async-signal-safe.
+        unsafe {
+            signal_hook_registry::register(signal, move || {
+                (&sender).write_all(&[1]).ok();
+            })?;
+        }
+    }
+    receiver.read_exact(&mut [0])
+}
+
+type HttpResult = Result<Response<Body>, http::Error>;
+
+fn save_person(repo: &dyn PersonRepository, id: Uuid, person: NewPerson) -> HttpResult {
+    match repo.update_person(id, person) {
+        Ok(Some(person)) => {
+            let person = serde_json::to_vec(&person).unwrap();
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(person))
+        }
+        Ok(None) => error_response(ApiError::not_found()),
+        Err(err) => persistence_error(err),
+    }
+}
+
+fn persistence_error(err: PersistenceError) -> HttpResult {
+    if let PersistenceError::DatabaseError(err) = &err {
+        error!(error = %err, "database error");
+    }
+    error_response(err.into())
+}
+
+/// The route serving a path, in the syntax of axum, which is also how requests are labelled in
+/// metrics.
+fn route(path: &str) -> Option<&'static str> {
+    let segments = path.split('/').skip(1).collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["pessoas"] => Some("/pessoas"),
+        ["pessoas", _] => Some("/pessoas/:id"),
+        ["contagem-pessoas"] => Some("/contagem-pessoas"),
+        ["metrics"] => Some("/metrics"),
+        ["health", "live"] => Some("/health/live"),
+        ["health", "ready"] => Some("/health/ready"),
+        _ => None,
+    }
+}
+
+/// The methods taken by a route.
+fn allowed_methods(route: &str) -> &'static str {
+    match route {
+        "/pessoas" => "GET,HEAD,POST",
+        "/pessoas/:id" => "GET,HEAD,PUT,PATCH,DELETE",
+        _ => "GET,HEAD",
+    }
+}
+
+fn error_response(err: ApiError) -> HttpResult {
+    Response::builder()
+        .status(err.status())
+        .header("content-type", "application/json")
+        .body(Body::from(err.to_json()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_labelled_with_their_route() {
+        assert_eq!(route("/pessoas"), Some("/pessoas"));
+        assert_eq!(
+            route("/pessoas/018a5b7e-7f4c-7000-8000-000000000001"),
+            Some("/pessoas/:id")
+        );
+        assert_eq!(route("/contagem-pessoas"), Some("/contagem-pessoas"));
+        assert_eq!(route("/health/ready"), Some("/health/ready"));
+        assert_eq!(route("/pessoas/1/2"), None);
+        assert_eq!(route("/"), None);
+    }
+
+    #[test]
+    fn every_route_takes_get() {
+        for path in [
+            "/pessoas",
+            "/pessoas/1",
+            "/contagem-pessoas",
+            "/metrics",
+            "/health/live",
+        ] {
+            let allowed = allowed_methods(route(path).unwrap());
+            assert!(allowed.starts_with("GET,HEAD"), "{path}: {allowed}");
+        }
+    }
 }
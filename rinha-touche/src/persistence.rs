@@ -1,14 +1,29 @@
-use std::{error::Error, fmt::Display, str::FromStr, sync::Arc, thread};
+use std::{
+    error::Error,
+    str::FromStr,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
 
-use dashmap::{DashMap, DashSet};
 use postgres::{
-    error::SqlState, fallible_iterator::FallibleIterator, Config as PgConfig, Error as PgError,
-    NoTls, Row,
+    fallible_iterator::FallibleIterator, types::ToSql, Client, Config as PgConfig, NoTls, Row,
 };
-use r2d2::{Error as PoolError, Pool};
+use r2d2::Pool;
 use r2d2_postgres::PostgresConnectionManager;
-use rinha_core::{NewPerson, Nick, Person, PersonName};
+use rinha_core::{
+    check_schema_version, normalize_stack, Backoff, BatchWrite, CacheWarmUp, CatchUp, CountMode,
+    HealthCheck, ListenerStatus, NewPerson, Nick, NickFilter, PendingPeople, PersistenceError,
+    PersistenceResult, Person, PersonCache, PersonChange, PersonName, PersonRepository, PoolStats,
+    Readiness, RepositoryOptions, RepositoryStats, SearchCache, SearchMode, SearchQuery,
+    StackMatch, WriteBehindConfig, WriteFailure, LAST_ID_QUERY, PERSON_CHANGES_CHANNEL,
+    READINESS_TIMEOUT, RESYNC_QUERY, WARM_UP_POLL_INTERVAL,
+};
 use time::Date;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 struct PersistedPerson {
@@ -25,8 +40,8 @@ impl TryFrom<Row> for PersistedPerson {
     fn try_from(value: Row) -> Result<Self, Self::Error> {
         Ok(Self {
             id: value.try_get("id")?,
-            name: value.try_get::<_, String>("name")?.try_into()?,
-            nick: value.try_get::<_, String>("nick")?.try_into()?,
+            name: PersonName::from_stored(value.try_get("name")?),
+            nick: Nick::from_stored(value.try_get("nick")?),
             birth_date: value.try_get("birth_date")?,
             stack: value.try_get("stack")?,
         })
@@ -45,115 +60,441 @@ impl From<PersistedPerson> for Person {
     }
 }
 
-pub type PersistenceResult<T> = Result<T, PersistenceError>;
-
-#[derive(Debug)]
-pub enum PersistenceError {
-    UniqueViolation,
-    DatabaseError(Box<dyn Error + Send + Sync>),
-}
-
-impl Display for PersistenceError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::UniqueViolation => write!(f, "unique constraint violated"),
-            Self::DatabaseError(err) => write!(f, "{}", err),
-        }
-    }
+pub struct PostgresRepository {
+    pool: Pool<PostgresConnectionManager<NoTls>>,
+    cache: Arc<PersonCache>,
+    nicks: Arc<NickFilter>,
+    searches: Arc<SearchCache>,
+    listener_status: Arc<ListenerStatus>,
+    write_behind: Option<WriteBehind>,
+    count_mode: CountMode,
+    cache_warm_up: CacheWarmUp,
 }
 
-impl From<PgError> for PersistenceError {
-    fn from(value: PgError) -> Self {
-        match value.code() {
-            Some(&SqlState::UNIQUE_VIOLATION) => PersistenceError::UniqueViolation,
-            _ => PersistenceError::DatabaseError(Box::new(value)),
-        }
-    }
+/// Where people created in write-behind mode wait to be written in batches.
+///
+/// The sender is taken away on shutdown, after which people are written right away.
+struct WriteBehind {
+    pending: Arc<PendingPeople>,
+    sender: RwLock<Option<mpsc::Sender<WriteBehindCommand>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
 }
 
-impl From<PoolError> for PersistenceError {
-    fn from(value: PoolError) -> Self {
-        PersistenceError::DatabaseError(Box::new(value))
-    }
-}
-
-pub struct PostgresRepository {
-    pool: Pool<PostgresConnectionManager<NoTls>>,
-    cache: Arc<DashMap<Uuid, Person>>,
-    nicks: Arc<DashSet<String>>,
+enum WriteBehindCommand {
+    Insert(Person),
+    Flush(mpsc::Sender<()>),
 }
 
 impl PostgresRepository {
-    pub fn connect(url: &str, pool_size: usize) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    /// Sets up the pool without waiting for the database, which may still be starting. The
+    /// cache is warmed up and changes are listened to in the background, retrying until the
+    /// database is there, and readiness fails until then. Servers should not accept traffic
+    /// before [`PostgresRepository::wait_for_warm_up`] returns.
+    pub fn connect(
+        url: &str,
+        pool_size: usize,
+        options: RepositoryOptions,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let config = PgConfig::from_str(url)?;
         let pool = r2d2::Pool::builder()
             .max_size(pool_size.try_into()?)
-            .build(PostgresConnectionManager::new(
-                PgConfig::from_str(url).unwrap(),
-                NoTls,
-            ))
-            .unwrap();
+            .build_unchecked(PostgresConnectionManager::new(config.clone(), NoTls));
 
-        let cache = Arc::new(DashMap::new());
-        let nicks = Arc::new(DashSet::new());
+        let cache = Arc::new(PersonCache::new(options.cache_limits));
+        let nicks = Arc::new(NickFilter::new(options.nick_filter));
+        let searches = Arc::new(SearchCache::new(options.search_cache));
+
+        let listener_status = Arc::new(ListenerStatus::default());
 
         thread::spawn({
-            let mut conn = pool.get()?;
             let cache = cache.clone();
             let nicks = nicks.clone();
+            let searches = searches.clone();
+            let listener_status = listener_status.clone();
+            let cache_warm_up = options.cache_warm_up;
             move || {
-                conn.execute("LISTEN person_created", &[])?;
-                let mut notifications = conn.notifications();
-                notifications.blocking_iter().for_each(|msg| {
-                    if let Ok(person) = serde_json::from_str::<Person>(msg.payload()) {
-                        nicks.insert(person.nick.as_str().to_owned());
-                        cache.insert(person.id, person);
+                let mut backoff = Backoff::default();
+                let mut catch_up = CatchUp::WarmUp(cache_warm_up);
+                loop {
+                    if let Err(err) = listen(
+                        &config,
+                        &cache,
+                        &nicks,
+                        &searches,
+                        &listener_status,
+                        &mut backoff,
+                        catch_up,
+                    ) {
+                        warn!(error = %err, "change listener disconnected");
                     }
-                    Ok(())
-                })?;
-                Ok::<_, Box<dyn Error + Send + Sync>>(())
+                    catch_up = catch_up.after_disconnect(listener_status.is_connected());
+                    listener_status.set_connected(false);
+                    thread::sleep(backoff.next_delay());
+                }
+            }
+        });
+
+        let write_behind = options.write_behind.map(|config| {
+            let pending = Arc::new(PendingPeople::new());
+            let (sender, receiver) = mpsc::channel();
+            let worker = thread::spawn({
+                let pool = pool.clone();
+                let pending = pending.clone();
+                move || {
+                    write_behind_worker(receiver, config, |batch| {
+                        write_batch(&pool, &pending, batch)
+                    })
+                }
+            });
+            WriteBehind {
+                pending,
+                sender: RwLock::new(Some(sender)),
+                worker: Mutex::new(Some(worker)),
             }
         });
 
-        Ok(Self { pool, cache, nicks })
+        Ok(Self {
+            pool,
+            cache,
+            nicks,
+            searches,
+            listener_status,
+            write_behind,
+            count_mode: options.count_mode,
+            cache_warm_up: options.cache_warm_up,
+        })
     }
 
-    pub fn create_person(&self, person: NewPerson) -> PersistenceResult<Uuid> {
-        if self.nicks.contains(person.nick.as_str()) {
-            return Err(PersistenceError::UniqueViolation);
+    /// Waits until the caches are warmed up, which they are once changes are first listened to.
+    /// Returns right away when they are not warmed up at all.
+    pub fn wait_for_warm_up(&self) {
+        if self.cache_warm_up == CacheWarmUp::Disabled {
+            return;
         }
+        while !self.listener_status.is_connected() {
+            thread::sleep(WARM_UP_POLL_INTERVAL);
+        }
+    }
 
-        let mut conn = self.pool.get()?;
+    fn listener_readiness(&self) -> HealthCheck {
+        match self.listener_status.is_connected() {
+            true => HealthCheck::passed(),
+            false => HealthCheck::failed("not listening to person changes"),
+        }
+    }
 
-        let stmt = conn.prepare(
+    fn pending(&self, id: &Uuid) -> Option<Person> {
+        self.write_behind
+            .as_ref()
+            .and_then(|write_behind| write_behind.pending.get(id))
+    }
+}
+
+/// Writes queued people in batches, whenever a batch fills up, the flush interval passes or a
+/// flush is requested, until every sender is gone.
+fn write_behind_worker(
+    receiver: mpsc::Receiver<WriteBehindCommand>,
+    config: WriteBehindConfig,
+    mut write: impl FnMut(Vec<Person>),
+) {
+    let batch_size = config.batch_size();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut deadline = Instant::now() + config.flush_interval;
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(WriteBehindCommand::Insert(person)) => {
+                batch.push(person);
+                if batch.len() >= batch_size {
+                    write(std::mem::take(&mut batch));
+                }
+            }
+            Ok(WriteBehindCommand::Flush(done)) => {
+                write(std::mem::take(&mut batch));
+                done.send(()).ok();
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                write(std::mem::take(&mut batch));
+                deadline = Instant::now() + config.flush_interval;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                write(std::mem::take(&mut batch));
+                return;
+            }
+        }
+    }
+}
+
+/// Writes a batch as [`BatchWrite`] tells, then forgets its people, dropping the reservations of
+/// those that could not be written so their nicks are free again.
+fn write_batch(
+    pool: &Pool<PostgresConnectionManager<NoTls>>,
+    pending: &PendingPeople,
+    batch: Vec<Person>,
+) {
+    let mut write = BatchWrite::new(batch);
+    while let Some(part) = write.next_part() {
+        let people = part.len();
+        match insert_people(pool, part) {
+            Ok(written) => write.written(&written),
+            Err((failure, err)) => {
+                error!(error = %err, people, "write-behind: batch failed");
+                if let Some(delay) = write.failed(failure) {
+                    thread::sleep(delay);
+                }
+            }
+        }
+    }
+
+    let outcome = write.finish();
+    if !outcome.dropped.is_empty() {
+        warn!(
+            dropped = outcome.dropped.len(),
+            "write-behind: people dropped"
+        );
+        release_nicks(pool, &outcome.dropped);
+    }
+    pending.release(outcome.settled.iter());
+}
+
+/// Inserts people with a single statement, returning the ids of those not skipped on conflicts.
+fn insert_people(
+    pool: &Pool<PostgresConnectionManager<NoTls>>,
+    people: &[Person],
+) -> Result<Vec<Uuid>, (WriteFailure, Box<dyn Error + Send + Sync>)> {
+    let rows = (0..people.len())
+        .map(|i| {
+            let n = i * 5;
+            format!(
+                "(${}, ${}, ${}, ${}, ${})",
+                n + 1,
+                n + 2,
+                n + 3,
+                n + 4,
+                n + 5
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query = format!(
+        "INSERT INTO people (id, name, nick, birth_date, stack) VALUES {rows} ON CONFLICT DO NOTHING RETURNING id"
+    );
+
+    let texts = people
+        .iter()
+        .map(|person| [person.name.as_str(), person.nick.as_str()])
+        .collect::<Vec<_>>();
+    let params = people
+        .iter()
+        .zip(&texts)
+        .flat_map(|(person, [name, nick])| -> [&(dyn ToSql + Sync); 5] {
+            [&person.id, name, nick, &person.birth_date, &person.stack]
+        })
+        .collect::<Vec<_>>();
+
+    let mut conn = pool
+        .get()
+        .map_err(|err| (WriteFailure::Transient, err.into()))?;
+    let rows = conn
+        .query(&query, &params)
+        .map_err(|err| (WriteFailure::from(&err), err.into()))?;
+    Ok(rows.iter().map(|row| row.get("id")).collect())
+}
+
+/// Frees the nicks reserved by people that were never written.
+fn release_nicks(pool: &Pool<PostgresConnectionManager<NoTls>>, people: &[Person]) {
+    let ids: Vec<Uuid> = people.iter().map(|person| person.id).collect();
+    let released = pool
+        .get()
+        .map_err(PersistenceError::from)
+        .and_then(|mut conn| {
+            Ok(conn.execute("DELETE FROM person_nicks WHERE id = ANY($1)", &[&ids])?)
+        });
+    if let Err(err) = released {
+        error!(error = %err, people = ids.len(), "write-behind: failed to release nicks");
+    }
+}
+
+/// Claims the nick of a person about to be written behind, failing if somebody already owns it.
+fn reserve_nick(
+    pool: &Pool<PostgresConnectionManager<NoTls>>,
+    person: &Person,
+) -> PersistenceResult<()> {
+    let reserved = pool.get()?.execute(
+        "INSERT INTO person_nicks (nick, id) VALUES ($1, $2) ON CONFLICT (nick) DO NOTHING",
+        &[&person.nick.as_str(), &person.id],
+    )?;
+
+    match reserved {
+        0 => Err(PersistenceError::UniqueViolation),
+        _ => Ok(()),
+    }
+}
+
+/// Streams existing people into the caches, as [`CacheWarmUp::query`] tells.
+fn warm_up(
+    client: &mut Client,
+    cache: &PersonCache,
+    nicks: &NickFilter,
+    listener_status: &ListenerStatus,
+    cache_warm_up: CacheWarmUp,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((query, newest)) = cache_warm_up.query() else {
+        return Ok(());
+    };
+    let mut rows = client.query_raw(query, newest)?;
+
+    let mut loaded = 0;
+    while let Some(row) = rows.next()? {
+        let person = Person::from(PersistedPerson::try_from(row)?);
+        CatchUp::load(person, cache, nicks, listener_status);
+
+        loaded += 1;
+        if loaded % 10_000 == 0 {
+            info!(loaded, "cache warm-up: people loaded");
+        }
+    }
+    info!(loaded, "cache warm-up: finished");
+
+    Ok(())
+}
+
+/// Applies person changes to the caches until the connection is lost, having caught up as
+/// [`CatchUp`] describes.
+///
+/// The connection is opened outside the pool: once it listens it must not serve anything else,
+/// and it is closed rather than returned when this fails.
+fn listen(
+    config: &PgConfig,
+    cache: &PersonCache,
+    nicks: &NickFilter,
+    searches: &SearchCache,
+    listener_status: &ListenerStatus,
+    backoff: &mut Backoff,
+    catch_up: CatchUp,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut conn = config.connect(NoTls)?;
+    conn.execute(&format!("LISTEN {PERSON_CHANGES_CHANNEL}"), &[])?;
+
+    cache.clear();
+    searches.clear();
+
+    match catch_up {
+        CatchUp::WarmUp(cache_warm_up) => {
+            warm_up(&mut conn, cache, nicks, listener_status, cache_warm_up)?;
+
+            if let Some(row) = conn.query_opt(LAST_ID_QUERY, &[])? {
+                listener_status.see_id(row.try_get(0)?);
+            }
+        }
+        CatchUp::Resync => {
+            let missed = conn.query(
+                RESYNC_QUERY,
+                &[&listener_status.last_id().unwrap_or_default()],
+            )?;
+            for row in missed {
+                let person = Person::from(PersistedPerson::try_from(row)?);
+                CatchUp::load(person, cache, nicks, listener_status);
+            }
+        }
+    }
+
+    listener_status.set_connected(true);
+    backoff.reset();
+
+    let mut notifications = conn.notifications();
+    notifications.blocking_iter().for_each(|msg| {
+        match serde_json::from_str::<PersonChange>(msg.payload()) {
+            Ok(change) => {
+                listener_status.observe(&change);
+                change.apply(cache, nicks, searches);
+            }
+            Err(err) => warn!(error = %err, payload = msg.payload(), "unreadable person change"),
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+impl PersonRepository for PostgresRepository {
+    fn create_person(&self, person: NewPerson) -> PersistenceResult<Uuid> {
+        if let Some(write_behind) = &self.write_behind {
+            // Held until the person is queued, so closing waits for it.
+            let open = write_behind.sender.read().unwrap();
+            let sender = open
+                .as_ref()
+                .filter(|_| self.listener_status.is_connected())
+                .filter(|_| !self.nicks.may_contain(person.nick.as_str()));
+            if let Some(sender) = sender {
+                let person = person.into_person(Uuid::now_v7());
+                if !write_behind.pending.reserve(&person) {
+                    return Err(PersistenceError::UniqueViolation);
+                }
+                if let Err(err) = reserve_nick(&self.pool, &person) {
+                    write_behind.pending.release([&person]);
+                    return Err(err);
+                }
+                self.nicks.insert(person.nick.as_str());
+
+                let id = person.id;
+                sender
+                    .send(WriteBehindCommand::Insert(person))
+                    .map_err(|err| PersistenceError::DatabaseError(err.to_string().into()))?;
+                return Ok(id);
+            }
+        }
+
+        let query = if self.nicks.may_contain(person.nick.as_str()) {
             "
             INSERT INTO
             people (id, name, nick, birth_date, stack)
             VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (nick) DO NOTHING
             RETURNING id
-            ",
-        )?;
+            "
+        } else {
+            "
+            INSERT INTO
+            people (id, name, nick, birth_date, stack)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+            "
+        };
+
+        let mut conn = self.pool.get()?;
+
+        let stmt = conn.prepare(query)?;
 
-        let result = conn.query_one(
+        let row = conn.query_opt(
             &stmt,
             &[
                 &Uuid::now_v7(),
                 &String::from(person.name),
                 &String::from(person.nick),
                 &person.birth_date,
-                &person
-                    .stack
-                    .map(|stack| stack.into_iter().map(String::from).collect::<Vec<_>>()),
+                &person.stack.map(normalize_stack),
             ],
         )?;
 
-        Ok(result.try_get(0)?)
+        match row {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Err(PersistenceError::UniqueViolation),
+        }
     }
 
-    pub fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
-        if let Some(person) = self.cache.get(&id).map(|entry| entry.value().clone()) {
+    fn find_person(&self, id: Uuid) -> PersistenceResult<Option<Person>> {
+        if let Some(person) = self.pending(&id) {
             return Ok(Some(person));
         }
 
+        if self.listener_status.is_connected() {
+            if let Some(person) = self.cache.get(&id) {
+                return Ok(Some(person));
+            }
+        }
+
         let mut conn = self.pool.get()?;
 
         let stmt = conn.prepare(
@@ -174,33 +515,360 @@ impl PostgresRepository {
         }
     }
 
-    pub fn search_people(&self, query: &str) -> PersistenceResult<Vec<Person>> {
+    fn update_person(&self, id: Uuid, person: NewPerson) -> PersistenceResult<Option<Person>> {
+        if self.pending(&id).is_some() {
+            self.flush()?;
+        }
+
         let mut conn = self.pool.get()?;
 
         let stmt = conn.prepare(
             "
-            SELECT id, name, nick, birth_date, stack
-            FROM people
-            WHERE search ILIKE $1
-            LIMIT 50
+            UPDATE people
+            SET name = $2, nick = $3, birth_date = $4, stack = $5
+            WHERE id = $1
+            RETURNING id, name, nick, birth_date, stack
             ",
         )?;
 
-        conn.query(&stmt, &[&format!("%{query}%")])?
+        let row = conn.query_opt(
+            &stmt,
+            &[
+                &id,
+                &String::from(person.name),
+                &String::from(person.nick),
+                &person.birth_date,
+                &person.stack.map(normalize_stack),
+            ],
+        )?;
+
+        match row {
+            Some(row) => {
+                let person = PersistedPerson::try_from(row)
+                    .map(Person::from)
+                    .map_err(PersistenceError::DatabaseError)?;
+                // The cached copy is left for the change listener to refresh, as it applies
+                // changes in the order they were committed.
+                self.cache.remove(&id);
+                Ok(Some(person))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_person(&self, id: Uuid) -> PersistenceResult<bool> {
+        if self.pending(&id).is_some() {
+            self.flush()?;
+        }
+
+        let mut conn = self.pool.get()?;
+
+        let stmt = conn.prepare("DELETE FROM people WHERE id = $1")?;
+        let deleted = conn.execute(&stmt, &[&id])?;
+
+        self.cache.remove(&id);
+
+        Ok(deleted > 0)
+    }
+
+    fn search_people(&self, query: &SearchQuery) -> PersistenceResult<Vec<Person>> {
+        let connected = self.listener_status.is_connected();
+        if connected {
+            if let Some(people) = self.searches.get(query) {
+                return Ok(people);
+            }
+        }
+
+        let mut sql =
+            String::from("SELECT id, name, nick, birth_date, stack FROM people WHERE TRUE");
+        let mut params: Vec<Box<dyn ToSql + Sync>> = Vec::new();
+        let mut bind = |value: Box<dyn ToSql + Sync>| {
+            params.push(value);
+            format!("${}", params.len())
+        };
+
+        // Placeholder of the term, when results are ranked by it.
+        let ranked_term = match (&query.term, query.mode) {
+            (None, _) => None,
+            (Some(term), SearchMode::Substring) => {
+                let pattern = bind(Box::new(term.contains_pattern()));
+                sql.push_str(&format!(" AND search ILIKE {pattern}"));
+                None
+            }
+            (Some(term), SearchMode::Trigram) => {
+                let term = bind(Box::new(term.as_str().to_owned()));
+                sql.push_str(&format!(" AND search % {term}"));
+                Some(term)
+            }
+            (Some(term), SearchMode::FullText) => {
+                let term = bind(Box::new(term.as_str().to_owned()));
+                sql.push_str(&format!(
+                    " AND search_document @@ PLAINTO_TSQUERY('portuguese', {term})"
+                ));
+                Some(term)
+            }
+        };
+        if let Some(name) = &query.name {
+            let pattern = bind(Box::new(name.contains_pattern()));
+            sql.push_str(&format!(" AND name ILIKE {pattern}"));
+        }
+        if let Some(nick) = &query.nick {
+            let nick = bind(Box::new(nick.clone()));
+            sql.push_str(&format!(" AND nick = {nick}"));
+        }
+        if !query.stack.is_empty() {
+            let stack = bind(Box::new(query.stack.clone()));
+            sql.push_str(&match query.stack_match {
+                StackMatch::Any => format!(" AND LOWER_ARRAY_IMMUTABLE(stack) && {stack}"),
+                StackMatch::All => format!(" AND LOWER_ARRAY_IMMUTABLE(stack) @> {stack}"),
+            });
+        }
+        if let Some(from) = query.born_from {
+            let from = bind(Box::new(from));
+            sql.push_str(&format!(" AND birth_date >= {from}"));
+        }
+        if let Some(until) = query.born_until {
+            let until = bind(Box::new(until));
+            sql.push_str(&format!(" AND birth_date <= {until}"));
+        }
+
+        match (&ranked_term, query.mode) {
+            (Some(term), SearchMode::Trigram) => {
+                sql.push_str(&format!(" ORDER BY SIMILARITY(search, {term}) DESC"));
+            }
+            (Some(term), SearchMode::FullText) => {
+                sql.push_str(&format!(
+                    " ORDER BY TS_RANK(search_document, PLAINTO_TSQUERY('portuguese', {term})) DESC"
+                ));
+            }
+            _ => {
+                if let Some(cursor) = query.cursor {
+                    let cursor = bind(Box::new(cursor));
+                    sql.push_str(&format!(" AND id > {cursor}"));
+                }
+                sql.push_str(" ORDER BY id");
+            }
+        }
+        let limit = bind(Box::new(query.limit() as i64));
+        sql.push_str(&format!(" LIMIT {limit}"));
+
+        let mut conn = self.pool.get()?;
+
+        let params = params
+            .iter()
+            .map(|param| param.as_ref())
+            .collect::<Vec<_>>();
+        let people = conn
+            .query(&sql, &params)?
             .into_iter()
             .map(|person| {
                 PersistedPerson::try_from(person)
                     .map(Person::from)
                     .map_err(PersistenceError::DatabaseError)
             })
-            .collect()
+            .collect::<PersistenceResult<Vec<_>>>()?;
+
+        if connected {
+            self.searches.insert(query, people.clone());
+        }
+
+        Ok(people)
     }
 
-    pub fn count_people(&self) -> PersistenceResult<u64> {
+    fn count_people(&self) -> PersistenceResult<u64> {
         let mut conn = self.pool.get()?;
-        let stmt = conn.prepare("SELECT COUNT(*) FROM people")?;
+        let stmt = conn.prepare(self.count_mode.query())?;
         let row = conn.query_one(&stmt, &[])?;
-        let count: i64 = row.try_get(0)?;
-        Ok(count.unsigned_abs())
+        Ok(CountMode::people(row.try_get(0)?))
+    }
+
+    fn flush(&self) -> PersistenceResult<()> {
+        let Some(write_behind) = &self.write_behind else {
+            return Ok(());
+        };
+
+        let (done, flushed) = mpsc::channel();
+        match write_behind.sender.read().unwrap().as_ref() {
+            Some(sender) => sender
+                .send(WriteBehindCommand::Flush(done))
+                .map_err(|err| PersistenceError::DatabaseError(err.to_string().into()))?,
+            // Closed, and so already flushed.
+            None => return Ok(()),
+        }
+        flushed
+            .recv()
+            .map_err(|err| PersistenceError::DatabaseError(err.into()))
+    }
+
+    fn close(&self) -> PersistenceResult<()> {
+        let Some(write_behind) = &self.write_behind else {
+            return Ok(());
+        };
+
+        // Waits for creations holding the sender, so none of them is queued after the worker
+        // stops, then lets the worker write what is left.
+        write_behind.sender.write().unwrap().take();
+        match write_behind.worker.lock().unwrap().take() {
+            Some(worker) => worker.join().map_err(|_| {
+                PersistenceError::DatabaseError("write-behind worker panicked".into())
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        let state = self.pool.state();
+        RepositoryStats {
+            pool: Some(PoolStats {
+                size: state.connections,
+                idle: state.idle_connections,
+                max_size: self.pool.max_size(),
+            }),
+            cache: Some(self.cache.stats()),
+            searches: Some(self.searches.stats()),
+            nicks: Some(self.nicks.stats()),
+            listener: Some(self.listener_status.stats()),
+        }
+    }
+
+    fn readiness(&self) -> Readiness {
+        let mut conn = match self.pool.get_timeout(READINESS_TIMEOUT) {
+            Ok(conn) => conn,
+            Err(err) => {
+                let database = HealthCheck::failed(err.to_string());
+                return Readiness {
+                    database: Some(database.clone()),
+                    migrations: Some(database),
+                    listener: Some(self.listener_readiness()),
+                };
+            }
+        };
+
+        let database = conn.simple_query("SELECT 1").map(|_| ());
+        let migrations = conn
+            .query_one("SELECT MAX(version) FROM schema_version", &[])
+            .and_then(|row| row.try_get::<_, Option<i32>>(0))
+            .map_err(|err| err.to_string())
+            .and_then(check_schema_version);
+
+        Readiness {
+            database: Some(database.into()),
+            migrations: Some(migrations.into()),
+            listener: Some(self.listener_readiness()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::macros::date;
+
+    use super::*;
+
+    fn person(n: u64) -> Person {
+        Person {
+            id: Uuid::from_u64_pair(0, n),
+            name: PersonName::from_stored(format!("Person {n}")),
+            nick: Nick::from_stored(format!("nick{n}")),
+            birth_date: date!(2000 - 01 - 01),
+            stack: None,
+        }
+    }
+
+    /// Starts a worker that only records the batches it is given.
+    fn worker(
+        batch_size: usize,
+    ) -> (
+        mpsc::Sender<WriteBehindCommand>,
+        Arc<Mutex<Vec<usize>>>,
+        JoinHandle<()>,
+    ) {
+        let config = WriteBehindConfig {
+            batch_size,
+            flush_interval: Duration::from_secs(3600),
+        };
+        let batches = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = mpsc::channel();
+        let written = batches.clone();
+        let handle = thread::spawn(move || {
+            write_behind_worker(receiver, config, |batch| {
+                if !batch.is_empty() {
+                    written.lock().unwrap().push(batch.len());
+                }
+            })
+        });
+        (sender, batches, handle)
+    }
+
+    fn flush(sender: &mpsc::Sender<WriteBehindCommand>) {
+        let (done, flushed) = mpsc::channel();
+        sender.send(WriteBehindCommand::Flush(done)).unwrap();
+        flushed.recv().unwrap();
+    }
+
+    #[test]
+    fn full_batches_are_written_right_away() {
+        let (sender, batches, _handle) = worker(2);
+        for n in 0..2 {
+            sender.send(WriteBehindCommand::Insert(person(n))).unwrap();
+        }
+        flush(&sender);
+        assert_eq!(*batches.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn a_flush_returns_once_everything_queued_is_written() {
+        let (sender, batches, _handle) = worker(10);
+        for n in 0..3 {
+            sender.send(WriteBehindCommand::Insert(person(n))).unwrap();
+        }
+        flush(&sender);
+        assert_eq!(*batches.lock().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn the_rest_is_written_when_the_senders_are_gone() {
+        let (sender, batches, handle) = worker(10);
+        sender.send(WriteBehindCommand::Insert(person(0))).unwrap();
+        drop(sender);
+        handle.join().unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![1]);
+    }
+
+    /// Runs against the database named by `TEST_DATABASE_URL`, migrated with `db/`, and passes
+    /// without doing anything when it is unset.
+    #[test]
+    fn wildcards_are_escaped_in_database_searches() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            return;
+        };
+        let repo = PostgresRepository::connect(&url, 2, RepositoryOptions::default()).unwrap();
+
+        let tag = Uuid::now_v7().simple().to_string()[24..].to_owned();
+        let ids = [format!("{tag}%_"), format!("{tag} plain")]
+            .iter()
+            .enumerate()
+            .map(|(n, name)| {
+                let json = format!(
+                    r#"{{"nome": "{name}", "apelido": "w{tag}{n}", "nascimento": "2000-01-01"}}"#
+                );
+                repo.create_person(NewPerson::from_json(json.as_bytes()).ok().unwrap())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let query = format!(r#"{{"t": "{tag}%_"}}"#);
+        let found = repo.search_people(&serde_json::from_str(&query).unwrap());
+        for id in &ids {
+            repo.delete_person(*id).unwrap();
+        }
+
+        let found = found.unwrap();
+        assert_eq!(
+            found.iter().map(|person| person.id).collect::<Vec<_>>(),
+            [ids[0]]
+        );
     }
 }